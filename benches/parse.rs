@@ -0,0 +1,85 @@
+// Compare parsing a log on a single thread with parsing it in parallel chunks.
+//
+// cargo bench --bench parse
+//
+// Set BENCH_LOG to the path of a rippled log to benchmark with a real log.
+// Otherwise a synthetic log is written to the temp directory.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use rs_log_analyzer::log_input::LogInput;
+use rs_log_analyzer::parse::{parse_parallel, parse_sequential};
+
+const SYNTHETIC_LINES: usize = 1_000_000;
+const RUNS: u32 = 3;
+
+fn write_synthetic_log(path: &std::path::Path) {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+    let jobs = ["InboundLedger", "processLedgerData", "AcceptLedger"];
+    for i in 0..SYNTHETIC_LINES {
+        let ts = format!(
+            "2021-Feb-13 {:02}:{:02}:{:02}.{:09} UTC",
+            (i / 360_000) % 24,
+            (i / 6000) % 60,
+            (i / 100) % 60,
+            (i % 100) * 10_000_000
+        );
+        match i % 4 {
+            0 => writeln!(
+                out,
+                "{} LoadMonitor:WRN Job latency {{\"job\": \"{}\", \"run(ms)\": {}, \"wait(ms)\": {}, \"jlogId\": 115}}",
+                ts,
+                jobs[i % jobs.len()],
+                i % 97,
+                i % 1013
+            ),
+            1 => writeln!(out, "{} TaggedCache:DBG LedgerCache target age set to {}", ts, i),
+            2 => writeln!(out, "{} LedgerConsensus:NFO Consensus round {} started", ts, i),
+            _ => writeln!(out, "{} Peer:WRN Peer {} disconnected", ts, i % 31),
+        }
+        .unwrap();
+    }
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut n = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        n = f();
+        best = best.min(start.elapsed());
+    }
+    (best, n)
+}
+
+fn main() {
+    let path = match std::env::var("BENCH_LOG") {
+        Ok(p) => std::path::PathBuf::from(p),
+        Err(_) => {
+            let p = std::env::temp_dir().join("rs-log-analyzer-bench.log");
+            write_synthetic_log(&p);
+            p
+        }
+    };
+    let input = LogInput::new(&path).unwrap();
+    let buf = input.as_str();
+
+    let (sequential, n_sequential) = time(|| parse_sequential(buf, 0, buf.len()).lines.len());
+    let (parallel, n_parallel) = time(|| parse_parallel(buf, 0, buf.len()).lines.len());
+    assert_eq!(n_sequential, n_parallel);
+
+    println!(
+        "{}: {} MB, {} lines",
+        path.display(),
+        buf.len() / (1024 * 1024),
+        n_sequential
+    );
+    println!("sequential: {:?}", sequential);
+    println!(
+        "parallel:   {:?} ({} threads, {:.1}x)",
+        parallel,
+        rayon::current_num_threads(),
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
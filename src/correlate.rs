@@ -0,0 +1,248 @@
+// Find the log messages that are logged around job latency spikes
+
+// A spike is a "Job latency" line whose wait time is over a threshold,
+// optionally only for one job. The lines logged within a window of time around
+// any spike are "near" a spike. All the lines except the spikes themselves are
+// grouped like the histogram (see `log_line_histogram::group`), and for every
+// group the share of the near lines in the group is compared with the share of
+// all the lines:
+//
+// ratio = (near lines in group / near lines) / (lines in group / lines)
+//
+// A ratio well over 1 means the message is logged much more often around
+// spikes than elsewhere in the log.
+
+use chrono::{Duration, NaiveDateTime};
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::job_latency::JobLatency;
+use crate::log_line::LogLine;
+use crate::template_miner::TemplateMiner;
+
+pub struct Spikes {
+    threshold: u64,      // wait time in ms
+    job: Option<String>, // only spikes of this job, or all jobs
+    window: Duration,    // lines this close to a spike are near it
+}
+
+struct GroupCorrelation<'a> {
+    line: LogLine<'a>, // the first line of the group
+    near: usize,       // lines in the group that are near a spike
+    count: usize,      // lines in the group
+    ratio: f64,
+}
+
+impl Spikes {
+    pub fn new(threshold: u64, job: Option<String>, window: Duration) -> Self {
+        Spikes {
+            threshold,
+            job,
+            window,
+        }
+    }
+
+    fn is_spike(&self, l: &LogLine) -> bool {
+        match JobLatency::from_log_line(l) {
+            Ok(Some(latency)) => {
+                latency.wait > self.threshold
+                    && self.job.as_ref().is_none_or(|job| *job == latency.job)
+            }
+            _ => false,
+        }
+    }
+
+    // The time ranges near spikes, sorted and merged where they overlap
+    fn windows(&self, log_lines: &BTreeSet<LogLine>) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut spikes: Vec<NaiveDateTime> = log_lines
+            .iter()
+            .filter(|l| self.is_spike(l))
+            .map(|l| l.time)
+            .collect();
+        spikes.sort();
+        let mut windows: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+        for t in spikes {
+            let (start, end) = (t - self.window, t + self.window);
+            match windows.last_mut() {
+                Some(last) if start <= last.1 => last.1 = end,
+                _ => windows.push((start, end)),
+            }
+        }
+        windows
+    }
+
+    // The groups that have lines near a spike, sorted by ratio, and the number
+    // of lines near a spike and of all the lines. The spikes aren't counted:
+    // every spike is near itself, which would make the job latency lines look
+    // correlated with the spikes.
+    fn correlations<'a>(
+        &self,
+        log_lines: &BTreeSet<LogLine<'a>>,
+        templates: Option<&TemplateMiner>,
+    ) -> (Vec<GroupCorrelation<'a>>, usize, usize) {
+        let windows = self.windows(log_lines);
+        let is_near = |t: NaiveDateTime| -> bool {
+            // the first window that ends at or after `t`
+            let i = windows.partition_point(|w| w.1 < t);
+            i < windows.len() && windows[i].0 <= t
+        };
+
+        let mut groups = Vec::new();
+        let mut total = 0;
+        let mut total_near = 0;
+        // ignored lines aren't part of any group
+        crate::log_line_histogram::group(log_lines, templates, |group| {
+            let lines: Vec<&LogLine> = group.iter().filter(|l| !self.is_spike(l)).collect();
+            if lines.is_empty() {
+                return;
+            }
+            let near = lines.iter().filter(|l| is_near(l.time)).count();
+            total += lines.len();
+            total_near += near;
+            groups.push(GroupCorrelation {
+                line: lines[0].clone(),
+                near,
+                count: lines.len(),
+                ratio: 0.0,
+            });
+        });
+
+        for g in &mut groups {
+            if total_near > 0 {
+                g.ratio = (g.near as f64 / total_near as f64) / (g.count as f64 / total as f64);
+            }
+        }
+        groups.retain(|g| g.near > 0);
+        groups.sort_by(|a, b| b.ratio.total_cmp(&a.ratio).then(b.near.cmp(&a.near)));
+        (groups, total_near, total)
+    }
+
+    pub fn write_correlation(
+        &self,
+        log_lines: &BTreeSet<LogLine>,
+        templates: Option<&TemplateMiner>,
+        out_file_name: &std::path::PathBuf,
+    ) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in correlate",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        let n_spikes = log_lines.iter().filter(|l| self.is_spike(l)).count();
+        let (groups, total_near, total) = self.correlations(log_lines, templates);
+
+        writeln!(
+            out_file,
+            "Spikes: {} job latency lines{} with wait over {}ms",
+            n_spikes,
+            match &self.job {
+                Some(job) => format!(" of {}", job),
+                None => String::new(),
+            },
+            self.threshold
+        )
+        .unwrap();
+        writeln!(
+            out_file,
+            "Lines within {}s of a spike: {} of {}",
+            self.window.num_milliseconds() as f64 / 1000.0,
+            total_near,
+            total
+        )
+        .unwrap();
+        writeln!(
+            out_file,
+            "\n{:>8} {:>8} {:>8} : line",
+            "Ratio", "Near", "Total"
+        )
+        .unwrap();
+        for g in &groups {
+            write!(out_file, "{:>8.2} {:>8} {:>8} : ", g.ratio, g.near, g.count).unwrap();
+            g.line.write_mixed_json(&mut out_file);
+            writeln!(out_file).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latency_line(time: &str, wait: u64) -> String {
+        format!(
+            "2021-Feb-12 17:{} UTC LoadMonitor:WRN Job latency {{\"job\": \"A\", \"run(ms)\": 1, \"wait(ms)\": {}, \"jlogId\": 1}}",
+            time, wait
+        )
+    }
+
+    fn line(time: &str, module_msg: &str) -> String {
+        format!("2021-Feb-12 17:{}.000000000 UTC {}", time, module_msg)
+    }
+
+    fn t(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("2021-02-12 17:{}", time), "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+    }
+
+    #[test]
+    fn windows_that_overlap_are_merged() {
+        let lines = [
+            latency_line("00:20.000000000", 5000),
+            latency_line("00:14.000000000", 5000),
+            latency_line("00:10.000000000", 10),
+            latency_line("01:00.000000000", 5000),
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let spikes = Spikes::new(1000, Option::None, Duration::seconds(10));
+        assert_eq!(
+            spikes.windows(&log_lines),
+            vec![(t("00:04"), t("00:30")), (t("00:50"), t("01:10"))]
+        );
+        let other_job = Spikes::new(1000, Some("B".to_string()), Duration::seconds(10));
+        assert!(other_job.windows(&log_lines).is_empty());
+    }
+
+    #[test]
+    fn ratio_is_the_share_of_near_lines_over_the_share_of_all_lines() {
+        let lines = [
+            latency_line("00:10.000000000", 5000),
+            latency_line("01:00.000000000", 10),
+            line("00:08", "Peer:NFO Peer disconnected"),
+            line("00:12", "Peer:NFO Peer disconnected"),
+            line("00:14", "Ledger:NFO Ledger closed"),
+            line("00:30", "Ledger:NFO Ledger closed"),
+            line("00:40", "Ledger:NFO Ledger closed"),
+            line("00:50", "Ledger:NFO Ledger closed"),
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let mut templates = TemplateMiner::new();
+        for l in &log_lines {
+            templates.add(l);
+        }
+        let spikes = Spikes::new(1000, Option::None, Duration::seconds(5));
+        for templates in [Option::None, Some(&templates)] {
+            let (groups, near, total) = spikes.correlations(&log_lines, templates);
+            // the spike isn't counted, and the other latency line is never near
+            // one
+            assert_eq!((near, total), (3, 7));
+            let rows: Vec<(&str, usize, usize, f64)> = groups
+                .iter()
+                .map(|g| (g.line.module, g.near, g.count, g.ratio))
+                .collect();
+            assert_eq!(
+                rows,
+                vec![
+                    ("Peer", 2, 2, (2.0 / 3.0) / (2.0 / 7.0)),
+                    ("Ledger", 1, 4, (1.0 / 3.0) / (4.0 / 7.0)),
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,343 @@
+// Statistics of the numeric fields of any structured log message
+
+// The lines with the message `msg` are grouped by the value of the `group_by`
+// field of their json data (or all put in one group), and every numeric field
+// in `fields` is summarized like the job latencies: min, percentiles, max,
+// mean, standard deviation, and a log-linear histogram. For example, the sizes
+// of the caches:
+//
+// --stats-msg "cache target size is set" --stats-group-by cacheName --stats-field size
+//
+// The `job-latency` preset reads the latencies like the job latency report
+// does, with `JobLatency::from_log_line`: the "Job latency" lines and the text
+// lines of older versions of rippled, grouped by `job`, with the `run(ms)`,
+// `wait(ms)` and `total(ms)` (run + wait) fields.
+//
+// Some modules log numbers as strings, so strings that hold a number are
+// accepted. The histograms only hold whole, non-negative numbers: fractions are
+// rounded, and a line with a negative value, or a field that is missing or not
+// a number, is counted as invalid and skipped.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::job_latency::{summary_json, write_summary_csv, write_summary_table, JobLatency};
+use crate::log_line::LogLine;
+use crate::log_linear_histogram::LogLinearHistogram;
+use crate::report_format::{csv_quote, ReportFormat};
+
+// Group of the lines without the `group_by` field
+const MISSING_GROUP: &str = "(missing)";
+
+// Presets that don't read the fields from the json data
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatsPreset {
+    JobLatency,
+}
+
+// Which lines and fields are summarized
+#[derive(Clone, Debug)]
+pub struct StatsSpec {
+    pub msg: String,
+    pub group_by: Option<String>,
+    pub fields: Vec<String>,
+    pub preset: Option<StatsPreset>,
+}
+
+// A preset of the stats, by name
+impl FromStr for StatsSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "job-latency" => Ok(StatsSpec {
+                msg: "Job latency".to_string(),
+                group_by: Some("job".to_string()),
+                fields: vec![
+                    "run(ms)".to_string(),
+                    "wait(ms)".to_string(),
+                    "total(ms)".to_string(),
+                ],
+                preset: Some(StatsPreset::JobLatency),
+            }),
+            _ => Err(format!("Unknown stats preset {}, expected job-latency", s)),
+        }
+    }
+}
+
+fn numeric_value(v: &serde_json::Value) -> Option<u64> {
+    let f = match v {
+        serde_json::Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                return Some(u);
+            }
+            n.as_f64()?
+        }
+        serde_json::Value::String(s) => {
+            if let Ok(u) = s.trim().parse::<u64>() {
+                return Some(u);
+            }
+            s.trim().parse::<f64>().ok()?
+        }
+        _ => return Option::None,
+    };
+    if f.is_finite() && f >= 0.0 {
+        Some(f.round() as u64)
+    } else {
+        Option::None
+    }
+}
+
+fn group_name(v: &serde_json::Value) -> String {
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        _ => v.to_string(),
+    }
+}
+
+// Collects the lines one at a time, so the lines don't all have to be in
+// memory at once
+pub struct FieldStats {
+    spec: StatsSpec,
+    // the histogram of every field, in the order of `spec.fields`, by group
+    groups: BTreeMap<String, Vec<LogLinearHistogram>>,
+    invalid: usize,
+    sub_bucket_bits: u32, // resolution of the histograms, see `LogLinearHistogram`
+}
+
+impl FieldStats {
+    pub fn new(spec: StatsSpec, sub_bucket_bits: u32) -> Self {
+        FieldStats {
+            spec,
+            groups: BTreeMap::new(),
+            invalid: 0,
+            sub_bucket_bits,
+        }
+    }
+
+    // Add the values of every field of a group
+    fn push(&mut self, group: String, values: Vec<u64>) {
+        let n_fields = values.len();
+        let sub_bucket_bits = self.sub_bucket_bits;
+        let collections = self
+            .groups
+            .entry(group)
+            .or_insert_with(|| vec![LogLinearHistogram::for_recording(sub_bucket_bits); n_fields]);
+        for (c, v) in collections.iter_mut().zip(values) {
+            c.record(v);
+        }
+    }
+
+    // Add the stats of other lines, for example of another node, that were
+    // collected with the same spec and sub-bucket bits
+    pub fn merge(&mut self, other: FieldStats) {
+        assert_eq!(self.sub_bucket_bits, other.sub_bucket_bits);
+        for (group, collections) in other.groups {
+            match self.groups.get_mut(&group) {
+                Some(existing) => {
+                    for (c, o) in existing.iter_mut().zip(&collections) {
+                        c.merge(o).unwrap();
+                    }
+                }
+                None => {
+                    self.groups.insert(group, collections);
+                }
+            }
+        }
+        self.invalid += other.invalid;
+    }
+
+    fn add_job_latency(&mut self, l: &LogLine) {
+        match JobLatency::from_log_line(l) {
+            Ok(Some(latency)) => {
+                let values = vec![latency.run, latency.wait, latency.run + latency.wait];
+                self.push(latency.job, values);
+            }
+            Ok(None) => (),
+            Err(()) => self.invalid += 1,
+        }
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        if self.spec.preset == Some(StatsPreset::JobLatency) {
+            self.add_job_latency(l);
+            return;
+        }
+        if l.msg != self.spec.msg {
+            return;
+        }
+        let m = match l.data_to_json_value() {
+            Some(serde_json::Value::Object(m)) => m,
+            _ => {
+                self.invalid += 1;
+                return;
+            }
+        };
+        let values: Option<Vec<u64>> = self
+            .spec
+            .fields
+            .iter()
+            .map(|f| m.get(f).and_then(numeric_value))
+            .collect();
+        let values = match values {
+            Some(values) => values,
+            None => {
+                self.invalid += 1;
+                return;
+            }
+        };
+        let group = match &self.spec.group_by {
+            Some(field) => m
+                .get(field)
+                .map(group_name)
+                .unwrap_or_else(|| MISSING_GROUP.to_string()),
+            None => "all".to_string(),
+        };
+        self.push(group, values);
+    }
+
+    fn write_text(&self, out_file: &mut std::fs::File) {
+        writeln!(out_file, "Message: {}", self.spec.msg).unwrap();
+        writeln!(out_file).unwrap();
+        for (group, collections) in &self.groups {
+            if let Some(field) = &self.spec.group_by {
+                writeln!(out_file, "Group: {}={}", field, group).unwrap();
+            }
+            writeln!(out_file, "Samples: {}", collections[0].count()).unwrap();
+            let rows: Vec<(&str, &LogLinearHistogram)> = self
+                .spec
+                .fields
+                .iter()
+                .map(|f| f.as_str())
+                .zip(collections.iter())
+                .collect();
+            write_summary_table(out_file, &rows);
+            for (field, c) in rows {
+                writeln!(out_file).unwrap();
+                writeln!(out_file, "{} histogram:", field).unwrap();
+                c.with_sub_bucket_bits(self.sub_bucket_bits)
+                    .write_ascii(out_file);
+            }
+            write!(out_file, "\n\n").unwrap();
+        }
+    }
+
+    // For example:
+    // {
+    //   "msg": "cache target size is set",
+    //   "group_by": "cacheName",
+    //   "groups": [
+    //     {"group": "LedgerCache", "count": 52, "size": {"min": 100, ..., "buckets": [...]}},
+    //     ...
+    //   ]
+    // }
+    fn json_stats(&self) -> serde_json::Value {
+        let groups: Vec<serde_json::Value> = self
+            .groups
+            .iter()
+            .map(|(group, collections)| {
+                let mut m = serde_json::Map::new();
+                m.insert("group".to_string(), serde_json::json!(group));
+                m.insert(
+                    "count".to_string(),
+                    serde_json::json!(collections[0].count()),
+                );
+                for (field, c) in self.spec.fields.iter().zip(collections) {
+                    m.insert(field.clone(), summary_json(c, self.sub_bucket_bits));
+                }
+                serde_json::Value::Object(m)
+            })
+            .collect();
+        serde_json::json!({
+            "msg": self.spec.msg,
+            "group_by": self.spec.group_by,
+            "groups": groups,
+        })
+    }
+
+    pub fn write(&self, out_file_name: &std::path::PathBuf, format: ReportFormat) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in field_stats",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        match format {
+            ReportFormat::Text => self.write_text(&mut out_file),
+            ReportFormat::Json => writeln!(
+                out_file,
+                "{}",
+                serde_json::to_string_pretty(&self.json_stats()).unwrap()
+            )
+            .unwrap(),
+            ReportFormat::Csv => {
+                writeln!(out_file, "group,field,statistic,value").unwrap();
+                for (group, collections) in &self.groups {
+                    for (field, c) in self.spec.fields.iter().zip(collections) {
+                        let key = format!("{},{}", csv_quote(group), csv_quote(field));
+                        write_summary_csv(&mut out_file, &key, c, self.sub_bucket_bits);
+                    }
+                }
+            }
+        }
+
+        if self.invalid > 0 {
+            eprintln!(
+                "Skipped {} \"{}\" lines with missing or invalid fields",
+                self.invalid, self.spec.msg
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job_latency::JobLatencyStats;
+
+    #[test]
+    fn job_latency_preset_matches_job_latency_stats() {
+        let lines = [
+            "2021-Feb-12 03:00:04.020060136 UTC LoadMonitor:WRN Job latency {\"job\": \"InboundLedger\", \"run(ms)\": 3, \"wait(ms)\": 1366, \"jlogId\": 115}",
+            "2021-Feb-12 03:00:05.020060136 UTC LoadMonitor:WRN Job: InboundLedger run: 2ms wait: 1455ms",
+            "2021-Feb-12 03:00:06.020060136 UTC LoadMonitor:WRN Job: processLedgerData run: 7ms wait: 0ms",
+        ];
+        let lines: Vec<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let mut stats = FieldStats::new("job-latency".parse().unwrap(), 2);
+        let mut latency = JobLatencyStats::new(2);
+        for l in &lines {
+            stats.add(l);
+            latency.add(l);
+        }
+        assert_eq!(stats.invalid, 0);
+        assert_eq!(stats.groups.len(), latency.stats.len());
+        for (job, collections) in &stats.groups {
+            let expected = &latency.stats[job];
+            assert_eq!(collections[0], expected.run);
+            assert_eq!(collections[1], expected.wait);
+            assert_eq!(collections[2], expected.total);
+        }
+        let mut total = LogLinearHistogram::for_recording(2);
+        total.record(1369);
+        total.record(1457);
+        assert_eq!(stats.groups["InboundLedger"][2], total);
+    }
+
+    #[test]
+    fn csv_group_and_field_names_are_quoted() {
+        let l = "2021-Feb-12 03:00:04.020060136 UTC LoadMonitor:WRN Job latency {\"job\": \"a,b\", \"run(ms)\": 3, \"wait(ms)\": 1366, \"jlogId\": 115}";
+        let mut stats = FieldStats::new("job-latency".parse().unwrap(), 2);
+        stats.add(&LogLine::new(l).unwrap());
+        let path = std::env::temp_dir().join(format!("field-stats-{}.csv", std::process::id()));
+        stats.write(&path, ReportFormat::Csv);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(csv.lines().any(|r| r == "\"a,b\",\"run(ms)\",max,3"));
+    }
+}
@@ -0,0 +1,476 @@
+// Filter expressions for selecting log lines
+
+// A filter is a boolean expression over the fields of a log line, for example:
+//
+// level>=WRN && module=~"Ledger.*" && data.job=="InboundLedger"
+//
+// Fields are `level`, `module`, `msg`, and `data.<path>` for a value inside
+// the json data. Path components that aren't simple words may be quoted:
+// `data."run(ms)" > 1000`. A field by itself is true if the line has it.
+//
+// The comparison operators are `==`, `!=`, `<`, `<=`, `>`, `>=`, `=~` (regex
+// match) and `!~` (regex does not match). Expressions may be combined with
+// `&&`, `||`, `!` and parentheses.
+//
+// Levels may be written either as they appear in the log (`WRN`) or by name
+// (`Warning`), and compare in order of severity. A comparison with a data
+// field the line does not have is false.
+
+use regex::Regex;
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::log_line::{LogLevel, LogLine};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Path(Vec<String>),
+    Str(String),
+    Num(f64),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+#[derive(Debug)]
+enum Field {
+    Level,
+    Module,
+    Msg,
+    Data(Vec<String>),
+}
+
+#[derive(Debug)]
+enum Value {
+    Level(LogLevel),
+    Str(String),
+    Num(f64),
+    Regex(Box<Regex>),
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Field),
+    Compare(Field, Op, Value),
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+fn parse_level(s: &str) -> Option<LogLevel> {
+    match s.to_lowercase().as_str() {
+        "trc" | "trace" => Some(LogLevel::Trace),
+        "dbg" | "debug" => Some(LogLevel::Debug),
+        "nfo" | "info" => Some(LogLevel::Info),
+        "wrn" | "warning" | "warn" => Some(LogLevel::Warning),
+        "err" | "error" => Some(LogLevel::Error),
+        "ftl" | "fatal" => Some(LogLevel::Fatal),
+        _ => Option::None,
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    // Read a quoted string starting at `i`. Return the string and the index after the closing quote.
+    let read_str = |mut i: usize| -> Result<(String, usize), String> {
+        let mut result = String::new();
+        i += 1;
+        while i < chars.len() && chars[i] != '"' {
+            if chars[i] == '\\' && i + 1 < chars.len() {
+                i += 1;
+            }
+            result.push(chars[i]);
+            i += 1;
+        }
+        if i >= chars.len() {
+            return Err(format!("Unterminated string in filter: {}", s));
+        }
+        Ok((result, i + 1))
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let (token, len) = match (c, next) {
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('=', Some('~')) => (Token::Op(Op::Match), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('!', Some('~')) => (Token::Op(Op::NotMatch), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('"', _) => {
+                let (string, end) = read_str(i)?;
+                tokens.push(Token::Str(string));
+                i = end;
+                continue;
+            }
+            _ if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let num: String = chars[start..i].iter().collect();
+                let num = num
+                    .parse::<f64>()
+                    .map_err(|_| format!("Bad number in filter: {}", num))?;
+                tokens.push(Token::Num(num));
+                continue;
+            }
+            _ if is_word(c) => {
+                let mut path = Vec::new();
+                loop {
+                    if i < chars.len() && chars[i] == '"' {
+                        let (segment, end) = read_str(i)?;
+                        path.push(segment);
+                        i = end;
+                    } else {
+                        let start = i;
+                        while i < chars.len() && is_word(chars[i]) {
+                            i += 1;
+                        }
+                        if start == i {
+                            return Err(format!("Bad field name in filter: {}", s));
+                        }
+                        path.push(chars[start..i].iter().collect());
+                    }
+                    if i < chars.len() && chars[i] == '.' {
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Path(path));
+                continue;
+            }
+            _ => return Err(format!("Unexpected character '{}' in filter: {}", c, s)),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let e = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(e),
+                    _ => Err("Missing ')' in filter".to_string()),
+                }
+            }
+            Some(Token::Path(path)) => self.comparison(path),
+            t => Err(format!("Expected a field in filter, found {:?}", t)),
+        }
+    }
+
+    fn comparison(&mut self, path: Vec<String>) -> Result<Expr, String> {
+        let field = match path[0].as_str() {
+            "level" if path.len() == 1 => Field::Level,
+            "module" if path.len() == 1 => Field::Module,
+            "msg" if path.len() == 1 => Field::Msg,
+            "data" => Field::Data(path[1..].to_vec()),
+            _ => return Err(format!("Unknown field in filter: {}", path.join("."))),
+        };
+
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return Ok(Expr::Exists(field)),
+        };
+        self.pos += 1;
+
+        let value = match self.next() {
+            Some(Token::Str(s)) => s,
+            Some(Token::Path(p)) if p.len() == 1 => p[0].clone(),
+            Some(Token::Num(n)) => {
+                if let Field::Data(_) = field {
+                    return Ok(Expr::Compare(field, op, Value::Num(n)));
+                }
+                n.to_string()
+            }
+            t => return Err(format!("Expected a value in filter, found {:?}", t)),
+        };
+
+        let value = if op == Op::Match || op == Op::NotMatch {
+            Value::Regex(Box::new(Regex::new(&value).map_err(|e| e.to_string())?))
+        } else if let Field::Level = field {
+            Value::Level(parse_level(&value).ok_or(format!("Bad log level in filter: {}", value))?)
+        } else {
+            Value::Str(value)
+        };
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!(
+                "Unexpected {:?} in filter: {}",
+                parser.tokens[parser.pos], s
+            ));
+        }
+        Ok(Filter { expr })
+    }
+}
+
+fn compare(ordering: Option<Ordering>, op: Op) -> bool {
+    match (ordering, op) {
+        (Some(o), Op::Eq) => o == Ordering::Equal,
+        (Some(o), Op::Ne) => o != Ordering::Equal,
+        (Some(o), Op::Lt) => o == Ordering::Less,
+        (Some(o), Op::Le) => o != Ordering::Greater,
+        (Some(o), Op::Gt) => o == Ordering::Greater,
+        (Some(o), Op::Ge) => o != Ordering::Less,
+        _ => false,
+    }
+}
+
+fn compare_str(s: &str, op: Op, value: &Value) -> bool {
+    match (op, value) {
+        (Op::Match, Value::Regex(re)) => re.is_match(s),
+        (Op::NotMatch, Value::Regex(re)) => !re.is_match(s),
+        (_, Value::Str(v)) => compare(Some(s.cmp(v.as_str())), op),
+        (_, Value::Num(n)) => compare(s.parse::<f64>().ok().and_then(|s| s.partial_cmp(n)), op),
+        _ => false,
+    }
+}
+
+fn compare_json(v: &serde_json::Value, op: Op, value: &Value) -> bool {
+    match v {
+        serde_json::Value::String(s) => compare_str(s, op, value),
+        v => compare_str(&v.to_string(), op, value),
+    }
+}
+
+fn lookup<'v>(data: &'v serde_json::Value, path: &[String]) -> Option<&'v serde_json::Value> {
+    let mut v = data;
+    for p in path {
+        v = match v {
+            serde_json::Value::Object(m) => m.get(p)?,
+            serde_json::Value::Array(a) => a.get(p.parse::<usize>().ok()?)?,
+            _ => return Option::None,
+        };
+    }
+    Some(v)
+}
+
+// The json data is only parsed if the filter looks at it, and at most once per line
+struct Context<'l, 'a> {
+    line: &'l LogLine<'a>,
+    data: Option<Option<serde_json::Value>>,
+}
+
+impl<'l, 'a> Context<'l, 'a> {
+    fn data(&mut self) -> Option<&serde_json::Value> {
+        let line = self.line;
+        self.data
+            .get_or_insert_with(|| line.data_to_json_value())
+            .as_ref()
+    }
+}
+
+impl Expr {
+    fn eval(&self, ctx: &mut Context) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(ctx) && b.eval(ctx),
+            Expr::Or(a, b) => a.eval(ctx) || b.eval(ctx),
+            Expr::Not(a) => !a.eval(ctx),
+            Expr::Exists(Field::Data(path)) => ctx.data().and_then(|d| lookup(d, path)).is_some(),
+            Expr::Exists(_) => true,
+            Expr::Compare(Field::Level, op, value) => match value {
+                Value::Level(level) => compare(Some(ctx.line.level.cmp(level)), *op),
+                _ => compare_str(&format!("{:?}", ctx.line.level), *op, value),
+            },
+            Expr::Compare(Field::Module, op, value) => compare_str(ctx.line.module, *op, value),
+            Expr::Compare(Field::Msg, op, value) => compare_str(ctx.line.msg, *op, value),
+            Expr::Compare(Field::Data(path), op, value) => {
+                match ctx.data().and_then(|d| lookup(d, path)) {
+                    Some(v) => compare_json(v, *op, value),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, line: &LogLine) -> bool {
+        let mut ctx = Context {
+            line,
+            data: Option::None,
+        };
+        self.expr.eval(&mut ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRUCTURED: &str = "2021-Feb-12 03:00:04.020060136 UTC LoadMonitor:WRN Job latency {\"job\": \"InboundLedger\", \"run(ms)\": 0, \"wait(ms)\": 1366, \"jlogId\": 115}";
+    const UNSTRUCTURED: &str =
+        "2021-Feb-05 13:52:54.660065778 UTC TaggedCache:DBG LedgerCache target age set to 180000000000";
+
+    fn matches(filter: &str, line: &str) -> bool {
+        let filter: Filter = filter.parse().unwrap();
+        filter.matches(&LogLine::new(line).unwrap())
+    }
+
+    #[test]
+    fn tokenize_operators_and_quoting() {
+        let tokens = tokenize(r#"data."run(ms)">=-1.5 && msg=~"a \"b\"" || !(level)"#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Path(vec!["data".to_string(), "run(ms)".to_string()]),
+                Token::Op(Op::Ge),
+                Token::Num(-1.5),
+                Token::And,
+                Token::Path(vec!["msg".to_string()]),
+                Token::Op(Op::Match),
+                Token::Str("a \"b\"".to_string()),
+                Token::Or,
+                Token::Not,
+                Token::LParen,
+                Token::Path(vec!["level".to_string()]),
+                Token::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // false && true || true
+        assert!(matches(
+            "level==ERR && module==LoadMonitor || msg==\"Job latency\"",
+            STRUCTURED
+        ));
+        // true || true && false
+        assert!(matches(
+            "module==LoadMonitor || msg==\"Job latency\" && level==ERR",
+            STRUCTURED
+        ));
+        assert!(!matches(
+            "(module==LoadMonitor || msg==\"Job latency\") && level==ERR",
+            STRUCTURED
+        ));
+        assert!(!matches("!module==LoadMonitor && level==WRN", STRUCTURED));
+    }
+
+    #[test]
+    fn level_data_and_regex_comparisons() {
+        assert!(matches("level>=WRN", STRUCTURED));
+        assert!(matches("level>=Warning", STRUCTURED));
+        assert!(!matches("level>=WRN", UNSTRUCTURED));
+        assert!(matches("data.\"wait(ms)\" > 1000", STRUCTURED));
+        assert!(!matches("data.\"wait(ms)\" > 2000", STRUCTURED));
+        assert!(matches("data.job==\"InboundLedger\"", STRUCTURED));
+        assert!(matches(
+            "module=~\"^Tagged.*\" && msg!~\"Ledger$\"",
+            UNSTRUCTURED
+        ));
+        // a comparison with a data field the line does not have is false
+        assert!(!matches("data.job!=\"InboundLedger\"", UNSTRUCTURED));
+        assert!(matches("data.jlogId", STRUCTURED));
+        assert!(!matches("data.missing", STRUCTURED));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in [
+            "",
+            "level==",
+            "(level==WRN",
+            "level==WRN)",
+            "msg==\"unterminated",
+            "level==Loud",
+            "size>1",
+            "module=~\"(\"",
+            "level==WRN &",
+            "level==WRN && && msg",
+        ] {
+            assert!(bad.parse::<Filter>().is_err(), "{:?} should not parse", bad);
+        }
+    }
+}
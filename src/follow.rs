@@ -0,0 +1,237 @@
+// Follow a growing log file, the way `tail -f` does
+
+// The file is polled for data appended since the last poll. Only complete
+// lines are parsed, and the last record read is held back until the next
+// record starts, because rippled may still be writing its continuation lines,
+// even after a poll that found no new data.
+//
+// Logs are rotated by renaming the file and starting a new one at the same
+// path, or by truncating the file. A rotation is noticed when the path names a
+// different file (a different inode), and a truncation when the file is
+// shorter than what was already read. Either way, the rest of the old data,
+// including the held back record, is parsed and the new file is read from the
+// start.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::log_line::LogLine;
+use crate::log_record::{count_lines, record_start, LogRecord, LogRecords};
+use crate::rejects::Rejects;
+
+pub struct Follower {
+    path: PathBuf,
+    file: File,
+    inode: u64,
+    offset: u64,        // file offset of the next byte to read
+    partial: Vec<u8>,   // the last line read, if it is not complete yet
+    text: String,       // complete lines that have not been parsed yet
+    text_offset: usize, // file offset of the start of `text`
+    line_number: usize, // line number of the start of `text`
+}
+
+impl Follower {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let inode = file.metadata()?.ino();
+        Ok(Follower {
+            path: path.to_path_buf(),
+            file,
+            inode,
+            offset: 0,
+            partial: Vec::new(),
+            text: String::new(),
+            text_offset: 0,
+            line_number: 1,
+        })
+    }
+
+    // Read everything appended to the file since the last read. Returns the
+    // number of bytes read.
+    fn read_appended(&mut self) -> std::io::Result<usize> {
+        let n = self.file.read_to_end(&mut self.partial)?;
+        self.offset += n as u64;
+        if let Some(i) = self.partial.iter().rposition(|c| *c == b'\n') {
+            let rest = self.partial.split_off(i + 1);
+            self.text.push_str(&String::from_utf8_lossy(&self.partial));
+            self.partial = rest;
+        }
+        Ok(n)
+    }
+
+    // Parse the complete records in `text`. Unless `all` is true, the last
+    // record is held back since more of its lines may still be written.
+    fn parse<F: FnMut(&LogLine)>(&mut self, all: bool, rejects: &mut Rejects, f: &mut F) {
+        if self.text.is_empty() {
+            return;
+        }
+        let end = if all {
+            self.text.len()
+        } else {
+            let last_line = match self.text[..self.text.len() - 1].rfind('\n') {
+                Some(i) => i + 1,
+                None => 0,
+            };
+            record_start(&self.text, last_line)
+        };
+
+        for r in LogRecords::with_line_number(&self.text, 0, end, self.line_number) {
+            match LogLine::new(r.text) {
+                Ok(l) => f(&l),
+                Err(err) => {
+                    let record = LogRecord {
+                        offset: self.text_offset + r.offset,
+                        ..r
+                    };
+                    rejects.add(&self.path, &record, &err);
+                }
+            }
+        }
+
+        self.line_number += count_lines(&self.text[..end]);
+        self.text_offset += end;
+        self.text.drain(..end);
+    }
+
+    // Parse whatever is left of the old file, including a last line without a
+    // newline, so the next file can be read from the start
+    fn restart<F: FnMut(&LogLine)>(&mut self, rejects: &mut Rejects, f: &mut F) {
+        if !self.partial.is_empty() {
+            self.text.push_str(&String::from_utf8_lossy(&self.partial));
+            self.text.push('\n');
+            self.partial.clear();
+        }
+        self.parse(true, rejects, f);
+        self.offset = 0;
+        self.text_offset = 0;
+        self.line_number = 1;
+    }
+
+    // Read the data appended since the last poll and call `f` with every
+    // record that is complete. Returns false if there was no new data.
+    pub fn poll<F: FnMut(&LogLine)>(
+        &mut self,
+        rejects: &mut Rejects,
+        mut f: F,
+    ) -> std::io::Result<bool> {
+        let mut n = self.read_appended()?;
+
+        // While a log is rotated, the path may briefly not exist
+        if let Ok(metadata) = std::fs::metadata(&self.path) {
+            if metadata.ino() != self.inode {
+                // the old file may have been written to before it was rotated
+                n += self.read_appended()?;
+                eprintln!("{} was rotated", self.path.display());
+                self.restart(rejects, &mut f);
+                self.file = File::open(&self.path)?;
+                self.inode = self.file.metadata()?.ino();
+                n += self.read_appended()?;
+            } else if metadata.len() < self.offset {
+                eprintln!("{} was truncated", self.path.display());
+                self.restart(rejects, &mut f);
+                self.file.seek(SeekFrom::Start(0))?;
+                n += self.read_appended()?;
+            }
+        }
+
+        self.parse(false, rejects, &mut f);
+        Ok(n > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn line(time: &str, msg: &str) -> String {
+        format!("2021-Feb-13 22:15:{} UTC Peer:NFO {}\n", time, msg)
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    // The messages, with any continuation lines, of the records of one poll
+    fn poll(follower: &mut Follower) -> Vec<String> {
+        let mut rejects = Rejects::new(&Option::None);
+        let mut records = Vec::new();
+        follower
+            .poll(&mut rejects, |l| {
+                records.push(if l.continuation.is_empty() {
+                    l.msg.to_string()
+                } else {
+                    format!("{} + {}", l.msg, l.continuation)
+                })
+            })
+            .unwrap();
+        records
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("follow-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        File::create(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn last_record_is_held_back_until_the_next_record_starts() {
+        let path = temp_log("hold");
+        let mut follower = Follower::new(&path).unwrap();
+        append(&path, &line("20.0", "one"));
+        append(&path, &line("21.0", "two"));
+        assert_eq!(poll(&mut follower), vec!["one"]);
+        // polls without new data don't give up on the continuation lines
+        assert!(poll(&mut follower).is_empty());
+        append(&path, "continued\n");
+        assert!(poll(&mut follower).is_empty());
+        assert!(poll(&mut follower).is_empty());
+        // a line without a newline is not complete
+        append(&path, "2021-Feb-13 22:15:22.0 UTC Peer:NFO th");
+        assert!(poll(&mut follower).is_empty());
+        append(&path, "ree\n");
+        assert_eq!(poll(&mut follower), vec!["two + continued"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_log_is_read_from_the_start() {
+        let path = temp_log("truncate");
+        let mut follower = Follower::new(&path).unwrap();
+        append(&path, &line("20.0", "one"));
+        append(&path, &line("21.0", "two"));
+        assert_eq!(poll(&mut follower), vec!["one"]);
+        File::create(&path).unwrap();
+        append(&path, &line("22.0", "3"));
+        append(&path, &line("23.0", "4"));
+        // the held back record of the old data is not lost
+        assert_eq!(poll(&mut follower), vec!["two", "3"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rotated_log_is_read_from_the_start() {
+        let path = temp_log("rotate");
+        let rotated = path.with_extension("log.1");
+        let mut follower = Follower::new(&path).unwrap();
+        append(&path, &line("20.0", "one"));
+        append(&path, &line("21.0", "two"));
+        assert_eq!(poll(&mut follower), vec!["one"]);
+        // written to the old file before it was rotated
+        append(&path, &line("22.0", "three"));
+        std::fs::rename(&path, &rotated).unwrap();
+        append(&path, &line("23.0", "four"));
+        append(&path, &line("24.0", "five"));
+        assert_eq!(poll(&mut follower), vec!["two", "three", "four"]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+// Count the lines of every group in every interval of time
+
+// The histogram gives one count per group, which hides when the lines were
+// written: a group that was quiet for hours and then logged 10k lines in a
+// minute looks the same as a group that logged a steady trickle. The text
+// report draws the counts of every group as a sparkline, scaled to the busiest
+// interval of the group, with a blank for intervals without any lines:
+//
+//    Count      Max : rate                                     : group
+//    10240     9800 :                                  ▁     █ : Peer:Warning Peer <*> disconnected
+//
+// Long logs have more intervals than fit on a line, so each character may sum
+// several intervals. The json and csv reports have the count of every
+// interval. The groups are those of the histogram diff (see
+// `HistogramSummary`).
+
+use std::cmp::Reverse;
+use std::io::Write;
+
+use crate::job_latency::format_ms;
+use crate::log_line_histogram::{GroupSummary, HistogramSummary};
+use crate::report_format::{csv_quote, ReportFormat};
+
+// Most characters of a sparkline
+const MAX_SPARKLINE_WIDTH: usize = 80;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+fn sparkline(counts: &[u32]) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|c| {
+            if *c == 0 {
+                ' '
+            } else {
+                // 1..=8, so the smallest count still shows
+                let level = (*c as u64 * SPARKS.len() as u64).div_ceil(max as u64);
+                SPARKS[level as usize - 1]
+            }
+        })
+        .collect()
+}
+
+// The counts of a group in every interval from `first` to `last`
+fn dense_counts(g: &GroupSummary, first: i64, last: i64, interval_ms: i64) -> Vec<u32> {
+    let n = ((last - first) / interval_ms + 1) as usize;
+    let mut counts = vec![0; n];
+    for (start, count) in &g.time_counts {
+        counts[((start - first) / interval_ms) as usize] = *count;
+    }
+    counts
+}
+
+// Sum every `n` counts, so the counts fit in a sparkline
+fn merge_counts(counts: &[u32], n: usize) -> Vec<u32> {
+    counts.chunks(n).map(|c| c.iter().sum()).collect()
+}
+
+pub fn write_rates(
+    histogram: &HistogramSummary,
+    out_file_name: &std::path::PathBuf,
+    format: ReportFormat,
+) {
+    let mut out_file = match std::fs::File::create(out_file_name) {
+        Ok(file) => file,
+        _ => {
+            eprintln!(
+                "Could not create file {} in group_rates",
+                out_file_name.display()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let interval_ms = histogram
+        .interval_ms
+        .expect("rates are recorded by the histogram");
+    let mut groups: Vec<&GroupSummary> = histogram.groups.iter().collect();
+    // like the histogram: by level, most severe first, then by count
+    groups.sort_by(|a, b| {
+        Reverse(a.level)
+            .cmp(&Reverse(b.level))
+            .then(b.count.cmp(&a.count))
+            .then_with(|| a.description.cmp(&b.description))
+    });
+    let first = groups
+        .iter()
+        .filter_map(|g| g.time_counts.keys().next())
+        .min()
+        .copied()
+        .unwrap_or(0);
+    let last = groups
+        .iter()
+        .filter_map(|g| g.time_counts.keys().next_back())
+        .max()
+        .copied()
+        .unwrap_or(0);
+    let rows: Vec<(&GroupSummary, Vec<u32>)> = groups
+        .into_iter()
+        .map(|g| (g, dense_counts(g, first, last, interval_ms)))
+        .collect();
+    let n_intervals = ((last - first) / interval_ms + 1) as usize;
+
+    match format {
+        ReportFormat::Text => {
+            let per_char = n_intervals.div_ceil(MAX_SPARKLINE_WIDTH);
+            writeln!(
+                out_file,
+                "From {} to {}, every character is {}s",
+                format_ms(first),
+                format_ms(last + interval_ms),
+                (per_char as i64 * interval_ms) as f64 / 1000.0
+            )
+            .unwrap();
+            let width = n_intervals.div_ceil(per_char);
+            writeln!(
+                out_file,
+                "\n{:>8} {:>8} : {:<w$} : group",
+                "Count",
+                "Max",
+                "rate",
+                w = width
+            )
+            .unwrap();
+            for (g, counts) in &rows {
+                let merged = merge_counts(counts, per_char);
+                writeln!(
+                    out_file,
+                    "{:>8} {:>8} : {} : {}",
+                    g.count,
+                    merged.iter().max().unwrap_or(&0),
+                    sparkline(&merged),
+                    g.description
+                )
+                .unwrap();
+            }
+        }
+        ReportFormat::Json => {
+            let starts: Vec<String> = (0..n_intervals)
+                .map(|i| format_ms(first + i as i64 * interval_ms))
+                .collect();
+            let groups: Vec<serde_json::Value> = rows
+                .iter()
+                .map(|(g, counts)| {
+                    serde_json::json!({
+                        "level": format!("{:?}", g.level),
+                        "group": g.description,
+                        "count": g.count,
+                        "counts": counts,
+                    })
+                })
+                .collect();
+            let v = serde_json::json!({
+                "interval_ms": interval_ms,
+                "starts": starts,
+                "groups": groups,
+            });
+            writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+        }
+        ReportFormat::Csv => {
+            write!(out_file, "level,group").unwrap();
+            for i in 0..n_intervals {
+                write!(out_file, ",{}", format_ms(first + i as i64 * interval_ms)).unwrap();
+            }
+            writeln!(out_file).unwrap();
+            for (g, counts) in &rows {
+                write!(out_file, "{:?},{}", g.level, csv_quote(&g.description)).unwrap();
+                for c in counts {
+                    write!(out_file, ",{}", c).unwrap();
+                }
+                writeln!(out_file).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_line::LogLine;
+    use crate::log_line_histogram::summarize;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn sparkline_is_scaled_to_the_busiest_interval() {
+        assert_eq!(sparkline(&[0, 1, 4, 8, 0]), " ▁▄█ ");
+        // the smallest count still shows
+        assert_eq!(sparkline(&[1, 1000]), "▁█");
+        assert_eq!(sparkline(&[3, 3]), "██");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn counts_are_dense_and_merged_to_fit() {
+        let g = GroupSummary {
+            key: String::new(),
+            level: crate::log_line::LogLevel::Info,
+            count: 5,
+            description: String::new(),
+            time_counts: BTreeMap::from([(2000, 2), (5000, 3)]),
+        };
+        assert_eq!(dense_counts(&g, 1000, 6000, 1000), vec![0, 2, 0, 0, 3, 0]);
+        assert_eq!(merge_counts(&[0, 2, 0, 0, 3, 0], 4), vec![2, 3]);
+        assert_eq!(merge_counts(&[1, 2, 3], 1), vec![1, 2, 3]);
+    }
+
+    fn write(lines: &[String], interval: chrono::Duration, format: ReportFormat) -> String {
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let summary = summarize(&log_lines, Option::None, Some(interval));
+        let path = std::env::temp_dir().join(format!("group-rates-{}", std::process::id()));
+        write_rates(&summary, &path, format);
+        let out = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        out
+    }
+
+    fn line(minute: u32, module_msg: &str) -> String {
+        format!(
+            "2021-Feb-13 22:{:02}:30.000000000 UTC {}",
+            minute, module_msg
+        )
+    }
+
+    #[test]
+    fn csv_has_a_row_per_group_and_a_column_per_interval() {
+        let lines = [
+            line(0, "Peer:WRN Peer 1 \"a, b\""),
+            line(2, "Peer:WRN Peer 2 \"a, b\""),
+            line(2, "Ledger:ERR Ledger 3 lost"),
+            line(3, "Peer:WRN Peer 4 \"a, b\""),
+        ];
+        let csv = write(&lines, chrono::Duration::minutes(1), ReportFormat::Csv);
+        assert_eq!(
+            csv.lines().collect::<Vec<&str>>(),
+            vec![
+                "level,group,2021-Feb-13 22:00:00 UTC,2021-Feb-13 22:01:00 UTC,2021-Feb-13 22:02:00 UTC,2021-Feb-13 22:03:00 UTC",
+                "Error,\"Ledger:Error Ledger <*> lost\",0,0,1,0",
+                "Warning,\"Peer:Warning Peer <*> \"\"a, b\"\"\",1,0,1,1",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_sparklines_merge_intervals_to_fit() {
+        // 161 intervals of a second, so every character is 3 intervals
+        let lines: Vec<String> = (0..=160)
+            .map(|s| {
+                format!(
+                    "2021-Feb-13 22:{:02}:{:02}.000000000 UTC Peer:WRN Peer {} left",
+                    s / 60,
+                    s % 60,
+                    s
+                )
+            })
+            .collect();
+        let text = write(&lines, chrono::Duration::seconds(1), ReportFormat::Text);
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            rows[0],
+            "From 2021-Feb-13 22:00:00 UTC to 2021-Feb-13 22:02:41 UTC, every character is 3s"
+        );
+        // the last character has 2 intervals
+        let sparkline = format!("{}▆", "█".repeat(53));
+        assert_eq!(
+            rows[3],
+            format!(
+                "     161        3 : {} : Peer:Warning Peer <*> left",
+                sparkline
+            )
+        );
+    }
+}
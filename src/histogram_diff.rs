@@ -0,0 +1,398 @@
+// Compare the histograms of a baseline log and a candidate log
+
+// The groups of the two logs are matched by their level, module and jlogId,
+// their template, or their level, module and message with the words that
+// contain numbers as wildcards (see `HistogramSummary`), which don't depend on
+// the other lines of the log. With templates, the templates of both logs are
+// mined with one miner, so a message has the same template in both. The logs
+// may cover different lengths of time, so the counts are compared as rates:
+// lines per minute over the time from the first to the last line of each log.
+// A group is new if it is only in the candidate, vanished if it is only in the
+// baseline, and otherwise changed by the ratio of the candidate rate to the
+// baseline rate.
+//
+// Groups are sorted by level, most severe first, and then by how much their
+// frequency changed: new and vanished groups first, then by the size of the
+// ratio, so x4 and x0.25 count as the same change.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::log_line::LogLevel;
+use crate::log_line_histogram::{GroupSummary, HistogramSummary};
+use crate::report_format::{csv_quote, ReportFormat};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Status {
+    New,
+    Vanished,
+    Changed,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::New => "new",
+            Status::Vanished => "vanished",
+            Status::Changed => "changed",
+        }
+    }
+}
+
+struct Row {
+    level: LogLevel,
+    description: String,
+    baseline: u32,
+    candidate: u32,
+    baseline_rate: f64, // lines per minute
+    candidate_rate: f64,
+    status: Status,
+}
+
+impl Row {
+    // candidate rate / baseline rate, only for changed groups
+    fn ratio(&self) -> Option<f64> {
+        match self.status {
+            Status::Changed => Some(self.candidate_rate / self.baseline_rate),
+            _ => Option::None,
+        }
+    }
+
+    // How much the frequency changed; new and vanished groups changed the most
+    fn change(&self) -> f64 {
+        match self.ratio() {
+            Some(r) => r.ln().abs(),
+            None => f64::INFINITY,
+        }
+    }
+
+    fn change_string(&self) -> String {
+        match self.ratio() {
+            Some(r) => format!("x{:.2}", r),
+            None => self.status.name().to_string(),
+        }
+    }
+}
+
+// Minutes from the first to the last line, at least one second so every log
+// has a rate
+fn minutes(h: &HistogramSummary) -> f64 {
+    (h.duration.num_milliseconds() as f64 / 60_000.0).max(1.0 / 60.0)
+}
+
+fn rows(baseline: &HistogramSummary, candidate: &HistogramSummary) -> Vec<Row> {
+    let baseline_minutes = minutes(baseline);
+    let candidate_minutes = minutes(candidate);
+    let mut groups: BTreeMap<String, (Option<&GroupSummary>, Option<&GroupSummary>)> =
+        BTreeMap::new();
+    for g in &baseline.groups {
+        groups.entry(g.key.clone()).or_default().0 = Some(g);
+    }
+    for g in &candidate.groups {
+        groups.entry(g.key.clone()).or_default().1 = Some(g);
+    }
+
+    let mut rows: Vec<Row> = groups
+        .into_values()
+        .map(|(b, c)| {
+            let status = match (&b, &c) {
+                (Some(_), Some(_)) => Status::Changed,
+                (None, Some(_)) => Status::New,
+                _ => Status::Vanished,
+            };
+            let baseline_count = b.as_ref().map_or(0, |g| g.count);
+            let candidate_count = c.as_ref().map_or(0, |g| g.count);
+            // describe the group as it is in the candidate, if it's there
+            let example = c.or(b).unwrap();
+            Row {
+                level: example.level,
+                description: example.description.clone(),
+                baseline: baseline_count,
+                candidate: candidate_count,
+                baseline_rate: baseline_count as f64 / baseline_minutes,
+                candidate_rate: candidate_count as f64 / candidate_minutes,
+                status,
+            }
+        })
+        .collect();
+
+    rows.sort_by(|a, b| {
+        Reverse(a.level)
+            .cmp(&Reverse(b.level))
+            .then(b.change().total_cmp(&a.change()))
+            .then_with(|| a.description.cmp(&b.description))
+    });
+    rows
+}
+
+pub fn write_diff(
+    baseline: &HistogramSummary,
+    candidate: &HistogramSummary,
+    out_file_name: &std::path::PathBuf,
+    format: ReportFormat,
+) {
+    let mut out_file = match std::fs::File::create(out_file_name) {
+        Ok(file) => file,
+        _ => {
+            eprintln!(
+                "Could not create file {} in histogram_diff",
+                out_file_name.display()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let rows = rows(baseline, candidate);
+
+    match format {
+        ReportFormat::Text => {
+            writeln!(
+                out_file,
+                "Baseline: {} lines in {:.1} minutes, candidate: {} lines in {:.1} minutes",
+                baseline.lines,
+                minutes(baseline),
+                candidate.lines,
+                minutes(candidate)
+            )
+            .unwrap();
+            let mut prev_level = Option::None;
+            for r in &rows {
+                if prev_level != Some(r.level) {
+                    write!(out_file, "\n{:?}\n", r.level).unwrap();
+                    writeln!(
+                        out_file,
+                        "{:>10} {:>10} {:>10} {:>10} {:>10} : group",
+                        "Baseline", "Candidate", "Base/min", "Cand/min", "Change"
+                    )
+                    .unwrap();
+                    prev_level = Some(r.level);
+                }
+                writeln!(
+                    out_file,
+                    "{:>10} {:>10} {:>10.2} {:>10.2} {:>10} : {}",
+                    r.baseline,
+                    r.candidate,
+                    r.baseline_rate,
+                    r.candidate_rate,
+                    r.change_string(),
+                    r.description
+                )
+                .unwrap();
+            }
+        }
+        ReportFormat::Json => {
+            let groups: Vec<serde_json::Value> = rows
+                .iter()
+                .map(|r| {
+                    serde_json::json!({
+                        "level": format!("{:?}", r.level),
+                        "group": r.description,
+                        "status": r.status.name(),
+                        "baseline_count": r.baseline,
+                        "candidate_count": r.candidate,
+                        "baseline_per_minute": r.baseline_rate,
+                        "candidate_per_minute": r.candidate_rate,
+                        "ratio": r.ratio(),
+                    })
+                })
+                .collect();
+            let v = serde_json::json!({
+                "baseline": {"lines": baseline.lines, "minutes": minutes(baseline)},
+                "candidate": {"lines": candidate.lines, "minutes": minutes(candidate)},
+                "groups": groups,
+            });
+            writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+        }
+        ReportFormat::Csv => {
+            writeln!(
+                out_file,
+                "level,group,status,baseline_count,candidate_count,baseline_per_minute,candidate_per_minute,ratio"
+            )
+            .unwrap();
+            for r in &rows {
+                writeln!(
+                    out_file,
+                    "{:?},{},{},{},{},{:.4},{:.4},{}",
+                    r.level,
+                    csv_quote(&r.description),
+                    r.status.name(),
+                    r.baseline,
+                    r.candidate,
+                    r.baseline_rate,
+                    r.candidate_rate,
+                    r.ratio()
+                        .map_or(String::new(), |ratio| format!("{:.4}", ratio))
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_line::LogLine;
+    use crate::log_line_histogram::summarize;
+    use std::collections::BTreeSet;
+
+    fn summary<S: AsRef<str>>(lines: &[S]) -> HistogramSummary {
+        let log_lines: BTreeSet<LogLine> = lines
+            .iter()
+            .map(|l| LogLine::new(l.as_ref()).unwrap())
+            .collect();
+        summarize(&log_lines, Option::None, Option::None)
+    }
+
+    fn changes(rows: &[Row]) -> Vec<(LogLevel, &str, u32, u32, String)> {
+        rows.iter()
+            .map(|r| {
+                (
+                    r.level,
+                    r.description.as_str(),
+                    r.baseline,
+                    r.candidate,
+                    r.change_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_are_new_vanished_or_changed() {
+        let baseline = summary(&[
+            "2021-Feb-13 22:00:00.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:00:10.000000000 UTC Peer:WRN Peer 2 disconnected",
+            "2021-Feb-13 22:00:20.000000000 UTC Ledger:NFO Ledger 5 closed",
+            "2021-Feb-13 22:01:00.000000000 UTC Ledger:NFO Ledger 6 closed",
+        ]);
+        let candidate = summary(&[
+            "2021-Feb-13 23:00:00.000000000 UTC Peer:WRN Peer 3 disconnected",
+            "2021-Feb-13 23:00:30.000000000 UTC Ledger:NFO Ledger 7 closed",
+            "2021-Feb-13 23:00:40.000000000 UTC Ledger:NFO Ledger 8 closed",
+            "2021-Feb-13 23:01:00.000000000 UTC Ledger:NFO Peer 9 connected",
+        ]);
+        assert_eq!(
+            changes(&rows(&baseline, &candidate)),
+            vec![
+                (
+                    LogLevel::Warning,
+                    "Peer:Warning Peer <*> disconnected",
+                    2,
+                    1,
+                    "x0.50".to_string()
+                ),
+                (
+                    LogLevel::Info,
+                    "Ledger:Info Peer <*> connected",
+                    0,
+                    1,
+                    "new".to_string()
+                ),
+                (
+                    LogLevel::Info,
+                    "Ledger:Info Ledger <*> closed",
+                    2,
+                    2,
+                    "x1.00".to_string()
+                ),
+            ]
+        );
+        let reversed = rows(&candidate, &baseline);
+        assert_eq!(reversed[1].status, Status::Vanished);
+        assert_eq!(reversed[1].change_string(), "vanished");
+        assert_eq!((reversed[1].baseline, reversed[1].candidate), (1, 0));
+    }
+
+    #[test]
+    fn counts_are_compared_as_rates() {
+        // 2 lines in 1 minute, and 4 lines in 4 minutes
+        let baseline = summary(&[
+            "2021-Feb-13 22:00:00.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:01:00.000000000 UTC Peer:WRN Peer 2 disconnected",
+        ]);
+        let candidate = summary(&[
+            "2021-Feb-13 22:00:00.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:01:00.000000000 UTC Peer:WRN Peer 2 disconnected",
+            "2021-Feb-13 22:02:00.000000000 UTC Peer:WRN Peer 3 disconnected",
+            "2021-Feb-13 22:04:00.000000000 UTC Peer:WRN Peer 4 disconnected",
+        ]);
+        let r = rows(&baseline, &candidate);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].baseline_rate, 2.0);
+        assert_eq!(r[0].candidate_rate, 1.0);
+        assert_eq!(r[0].ratio(), Some(0.5));
+
+        // a log of one line lasts a second
+        let one = summary(&["2021-Feb-13 22:00:00.000000000 UTC Peer:WRN Peer 1 disconnected"]);
+        assert_eq!(minutes(&one), 1.0 / 60.0);
+        assert_eq!(rows(&one, &candidate)[0].baseline_rate, 60.0);
+    }
+
+    #[test]
+    fn rows_are_sorted_by_level_then_by_size_of_change() {
+        // `n` lines of the module, one a second, so both logs last 3 seconds
+        let lines = |module: &str, level: &str, n: usize| -> Vec<String> {
+            (0..n)
+                .map(|i| {
+                    format!(
+                        "2021-Feb-13 22:00:0{}.000000000 UTC {}:{} Event happened",
+                        i, module, level
+                    )
+                })
+                .collect()
+        };
+        let baseline = summary(
+            &[
+                lines("A", "NFO", 1),
+                lines("B", "NFO", 4),
+                lines("C", "NFO", 2),
+                lines("E", "ERR", 1),
+            ]
+            .concat(),
+        );
+        let candidate = summary(
+            &[
+                lines("A", "NFO", 4),
+                lines("B", "NFO", 1),
+                lines("C", "NFO", 3),
+                lines("D", "NFO", 1),
+                lines("E", "ERR", 1),
+            ]
+            .concat(),
+        );
+        let order: Vec<(String, String)> = rows(&baseline, &candidate)
+            .iter()
+            .map(|r| (r.description[..1].to_string(), r.change_string()))
+            .collect();
+        // x4 and x0.25 are the same size of change, and then go by description
+        let expected = [
+            ("E", "x1.00"),
+            ("D", "new"),
+            ("A", "x4.00"),
+            ("B", "x0.25"),
+            ("C", "x1.50"),
+        ];
+        assert_eq!(
+            order,
+            expected
+                .iter()
+                .map(|(d, c)| (d.to_string(), c.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn csv_group_names_are_quoted() {
+        let baseline = summary(&["2021-Feb-13 22:00:00.000000000 UTC Peer:WRN Peer \"a, b\" left"]);
+        let path = std::env::temp_dir().join(format!("histogram-diff-{}.csv", std::process::id()));
+        write_diff(&baseline, &baseline, &path, ReportFormat::Csv);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            csv.lines().nth(1).unwrap(),
+            "Warning,\"Peer:Warning Peer \"\"a, b\"\" left\",changed,1,1,60.0000,60.0000,1.0000"
+        );
+    }
+}
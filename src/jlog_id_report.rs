@@ -0,0 +1,212 @@
+// Report the jlogIds that are used inconsistently
+
+// Every structured line has a `jlogId` that identifies the logging statement in
+// rippled that wrote it, so all the lines with the same id should have the same
+// message, module and level. An id whose lines have different messages, or
+// that shows up under several modules or levels, points at a logging bug in
+// rippled, like an id that was copied to a new logging statement.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::log_line::{LogLevel, LogLine};
+use crate::report_format::{csv_quote, ReportFormat};
+
+#[derive(Default)]
+struct JlogIdUses {
+    count: usize,
+    msgs: BTreeMap<String, usize>,
+    modules: BTreeMap<String, usize>,
+    levels: BTreeMap<LogLevel, usize>,
+}
+
+impl JlogIdUses {
+    fn is_consistent(&self) -> bool {
+        self.msgs.len() == 1 && self.modules.len() == 1 && self.levels.len() == 1
+    }
+
+    // The kinds of values that differ and their counts, for example
+    // ("msg", [("Job latency", 40), ("Job latncy", 2)])
+    fn differences(&self) -> Vec<(&'static str, Vec<(String, usize)>)> {
+        let to_vec = |m: &BTreeMap<String, usize>| -> Vec<(String, usize)> {
+            m.iter().map(|(k, v)| (k.clone(), *v)).collect()
+        };
+        let mut result = Vec::new();
+        if self.msgs.len() > 1 {
+            result.push(("msg", to_vec(&self.msgs)));
+        }
+        if self.modules.len() > 1 {
+            result.push(("module", to_vec(&self.modules)));
+        }
+        if self.levels.len() > 1 {
+            let levels = self
+                .levels
+                .iter()
+                .map(|(k, v)| (format!("{:?}", k), *v))
+                .collect();
+            result.push(("level", levels));
+        }
+        result
+    }
+}
+
+#[derive(Default)]
+pub struct JlogIdReport {
+    ids: BTreeMap<u64, JlogIdUses>,
+}
+
+impl JlogIdReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        if let Some(id) = l.jlog_id() {
+            let uses = self.ids.entry(id).or_default();
+            uses.count += 1;
+            *uses.msgs.entry(l.msg.to_string()).or_insert(0) += 1;
+            *uses.modules.entry(l.module.to_string()).or_insert(0) += 1;
+            *uses.levels.entry(l.level).or_insert(0) += 1;
+        }
+    }
+
+    pub fn write(&self, out_file_name: &std::path::PathBuf, format: ReportFormat) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in jlog_id_report",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        let inconsistent: Vec<(&u64, &JlogIdUses)> = self
+            .ids
+            .iter()
+            .filter(|(_, uses)| !uses.is_consistent())
+            .collect();
+
+        match format {
+            ReportFormat::Text => {
+                writeln!(
+                    out_file,
+                    "jlogIds: {}, inconsistent: {}",
+                    self.ids.len(),
+                    inconsistent.len()
+                )
+                .unwrap();
+                for (id, uses) in &inconsistent {
+                    writeln!(out_file, "\njlogId {}: {} lines", id, uses.count).unwrap();
+                    for (kind, values) in uses.differences() {
+                        writeln!(out_file, "    {}:", kind).unwrap();
+                        for (value, count) in values {
+                            writeln!(out_file, "{:>12} : {}", count, value).unwrap();
+                        }
+                    }
+                }
+            }
+            ReportFormat::Json => {
+                let ids: Vec<serde_json::Value> = inconsistent
+                    .iter()
+                    .map(|(id, uses)| {
+                        let mut m = serde_json::Map::new();
+                        m.insert("jlog_id".to_string(), serde_json::json!(id));
+                        m.insert("count".to_string(), serde_json::json!(uses.count));
+                        for (kind, values) in uses.differences() {
+                            let values: Vec<serde_json::Value> = values
+                                .iter()
+                                .map(|(value, count)| {
+                                    serde_json::json!({"value": value, "count": count})
+                                })
+                                .collect();
+                            m.insert(format!("{}s", kind), serde_json::json!(values));
+                        }
+                        serde_json::Value::Object(m)
+                    })
+                    .collect();
+                let v = serde_json::json!({
+                    "jlog_ids": self.ids.len(),
+                    "inconsistent": ids,
+                });
+                writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+            }
+            ReportFormat::Csv => {
+                writeln!(out_file, "jlog_id,kind,value,count").unwrap();
+                for (id, uses) in &inconsistent {
+                    for (kind, values) in uses.differences() {
+                        for (value, count) in values {
+                            // values are quoted, since messages may have commas
+                            writeln!(out_file, "{},{},{},{}", id, kind, csv_quote(&value), count)
+                                .unwrap();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(module_level: &str, msg: &str, id: u64) -> String {
+        format!(
+            "2021-Feb-12 17:00:00.000000000 UTC {} {} {{\"jlogId\": {}}}",
+            module_level, msg, id
+        )
+    }
+
+    fn report(lines: &[String]) -> JlogIdReport {
+        let mut report = JlogIdReport::new();
+        for l in lines {
+            report.add(&LogLine::new(l).unwrap());
+        }
+        report
+    }
+
+    #[test]
+    fn ids_with_different_msgs_modules_or_levels_are_inconsistent() {
+        let report = report(&[
+            line("Peer:NFO", "Peer connected", 1),
+            line("Peer:NFO", "Peer connected", 1),
+            line("Peer:NFO", "Peer connected", 2),
+            line("Peer:NFO", "Peer, disconnected", 2),
+            line("Peer:NFO", "Ledger closed", 3),
+            line("Ledger:NFO", "Ledger closed", 3),
+            line("Peer:NFO", "Peer left", 4),
+            line("Peer:WRN", "Peer left", 4),
+            "2021-Feb-12 17:00:00.000000000 UTC Peer:NFO no id".to_string(),
+        ]);
+        assert_eq!(report.ids.len(), 4);
+        assert!(report.ids[&1].is_consistent());
+        assert_eq!(report.ids[&1].count, 2);
+        let differences = |id: u64| report.ids[&id].differences();
+        assert_eq!(
+            differences(2),
+            vec![(
+                "msg",
+                vec![
+                    ("Peer connected".to_string(), 1),
+                    ("Peer, disconnected".to_string(), 1)
+                ]
+            )]
+        );
+        assert_eq!(differences(3)[0].0, "module");
+        assert_eq!(differences(3).len(), 1);
+        assert_eq!(differences(4)[0].0, "level");
+        assert_eq!(differences(4).len(), 1);
+
+        let path = std::env::temp_dir().join(format!("jlog-ids-{}.csv", std::process::id()));
+        report.write(&path, ReportFormat::Csv);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 7);
+        assert_eq!(rows[0], "jlog_id,kind,value,count");
+        assert_eq!(rows[2], "2,msg,\"Peer, disconnected\",1");
+        assert!(!csv.contains("\n1,"));
+    }
+}
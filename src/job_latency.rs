@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
 
-use crate::log_line::LogLine;
+use crate::log_line::{LogLine, TIMESTAMP_FORMAT};
+use crate::log_linear_histogram::LogLinearHistogram;
+use crate::report_format::{csv_quote, ReportFormat};
 
-struct JobLatency {
-    job: String,
-    run: u64,  // run time ms
-    wait: u64, // wait time ms
+pub(crate) struct JobLatency {
+    pub(crate) job: String,
+    pub(crate) run: u64,  // run time ms
+    pub(crate) wait: u64, // wait time ms
 }
 
 impl JobLatency {
@@ -24,142 +29,676 @@ impl JobLatency {
             let wait = m.get("wait(ms)")?.as_u64()?;
             return Some(JobLatency { job, run, wait });
         }
-        return Option::None;
+        Option::None
+    }
+
+    // Older versions of rippled log the latency as text, for example:
+    // LoadMonitor:WRN Job: processLedgerData run: 2ms wait: 1455ms
+    fn from_legacy_msg(msg: &str) -> Option<Self> {
+        lazy_static! {
+            static ref LEGACY_RE: Regex = Regex::new(
+                r"(?x)
+                # The x flag enables insignificant whitespace mode (allowing comments)
+                ^Job:\ (?P<job>\S+)
+                \ run:\ (?P<run>\d+)ms
+                \ wait:\ (?P<wait>\d+)ms$
+"
+            )
+            .unwrap();
+        }
+        let caps = LEGACY_RE.captures(msg)?;
+        Some(JobLatency {
+            job: caps["job"].to_string(),
+            run: caps["run"].parse().ok()?,
+            wait: caps["wait"].parse().ok()?,
+        })
+    }
+
+    // The latency in a "Job latency" line or a legacy "Job: ..." line.
+    // `Ok(None)` for any other line, and `Err` for a "Job latency" line with
+    // invalid data or a "Job: ..." line that doesn't match the legacy format.
+    pub(crate) fn from_log_line(l: &LogLine) -> Result<Option<Self>, ()> {
+        if l.json_data.is_empty() && l.msg.starts_with("Job: ") {
+            return JobLatency::from_legacy_msg(l.msg).map(Some).ok_or(());
+        }
+        if l.msg != "Job latency" {
+            return Ok(Option::None);
+        }
+        match l.data_to_json_value().and_then(JobLatency::from_json_value) {
+            Some(latency) => Ok(Some(latency)),
+            None => Err(()),
+        }
     }
 }
 
-struct JobLatencyCollection {
-    run: Vec<u64>,
-    wait: Vec<u64>,
+// Nearest rank percentile of sorted values
+pub(crate) fn percentile(sorted: &[u64], p: f64) -> u64 {
+    assert!(!sorted.is_empty());
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Summary statistics of a non-empty collection of latencies
+pub(crate) struct Summary {
+    pub(crate) min: u64,
+    pub(crate) p50: u64,
+    pub(crate) p90: u64,
+    pub(crate) p99: u64,
+    pub(crate) p999: u64,
+    pub(crate) max: u64,
+    pub(crate) mean: f64,
+    pub(crate) std_dev: f64,
+}
+
+impl Summary {
+    pub(crate) fn new(sorted: &[u64]) -> Self {
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().map(|v| *v as f64).sum::<f64>() / n;
+        let variance = sorted
+            .iter()
+            .map(|v| (*v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Summary {
+            min: sorted[0],
+            p50: percentile(sorted, 50.0),
+            p90: percentile(sorted, 90.0),
+            p99: percentile(sorted, 99.0),
+            p999: percentile(sorted, 99.9),
+            max: sorted[sorted.len() - 1],
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+
+    // The summary of a non-empty histogram, see `LogLinearHistogram::percentile`
+    pub(crate) fn from_histogram(h: &LogLinearHistogram) -> Self {
+        Summary {
+            min: h.min(),
+            p50: h.percentile(50.0),
+            p90: h.percentile(90.0),
+            p99: h.percentile(99.0),
+            p999: h.percentile(99.9),
+            max: h.max(),
+            mean: h.mean(),
+            std_dev: h.std_dev(),
+        }
+    }
+}
+
+// A table with the summary of every non-empty histogram, one row per
+// histogram, for example:
+//             Min      p50      p90      p99    p99.9      Max        Ave    Std Dev
+// Run           0       33      118      217      320      383      48.53      48.67
+pub(crate) fn write_summary_table<W: Write>(out: &mut W, rows: &[(&str, &LogLinearHistogram)]) {
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+    writeln!(
+        out,
+        "{:<w$} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10} {:>10}",
+        "",
+        "Min",
+        "p50",
+        "p90",
+        "p99",
+        "p99.9",
+        "Max",
+        "Ave",
+        "Std Dev",
+        w = width
+    )
+    .unwrap();
+    for (name, h) in rows {
+        let s = Summary::from_histogram(h);
+        writeln!(
+            out,
+            "{:<w$} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10.2} {:>10.2}",
+            name,
+            s.min,
+            s.p50,
+            s.p90,
+            s.p99,
+            s.p999,
+            s.max,
+            s.mean,
+            s.std_dev,
+            w = width
+        )
+        .unwrap();
+    }
+}
+
+// The summary and buckets of a non-empty histogram, for example:
+// {"min": 0, "p50": 33, ..., "buckets": [{"low": 0, "high": 0, "count": 5}, ...]}
+pub(crate) fn summary_json(h: &LogLinearHistogram, sub_bucket_bits: u32) -> serde_json::Value {
+    let s = Summary::from_histogram(h);
+    serde_json::json!({
+        "min": s.min,
+        "p50": s.p50,
+        "p90": s.p90,
+        "p99": s.p99,
+        "p99.9": s.p999,
+        "max": s.max,
+        "mean": s.mean,
+        "std_dev": s.std_dev,
+        "buckets": h.with_sub_bucket_bits(sub_bucket_bits).to_json_value(),
+    })
+}
+
+// One csv row per statistic of a non-empty histogram:
+// <key>,<statistic>,<value>. Bucket rows are named after the bucket range, for
+// example bucket_1024_1279.
+pub(crate) fn write_summary_csv<W: Write>(
+    out: &mut W,
+    key: &str,
+    h: &LogLinearHistogram,
+    sub_bucket_bits: u32,
+) {
+    let s = Summary::from_histogram(h);
+    let mut row = |statistic: &str, value: String| {
+        writeln!(out, "{},{},{}", key, statistic, value).unwrap();
+    };
+    row("count", h.count().to_string());
+    row("min", s.min.to_string());
+    row("p50", s.p50.to_string());
+    row("p90", s.p90.to_string());
+    row("p99", s.p99.to_string());
+    row("p99.9", s.p999.to_string());
+    row("max", s.max.to_string());
+    row("mean", format!("{:.2}", s.mean));
+    row("std_dev", format!("{:.2}", s.std_dev));
+    for b in h.with_sub_bucket_bits(sub_bucket_bits).buckets() {
+        row(&format!("bucket_{}_{}", b.low, b.high), b.count.to_string());
+    }
+}
+
+// The run, wait and total latencies of a job, recorded into histograms (see
+// `LogLinearHistogram::for_recording`)
+pub(crate) struct JobLatencyCollection {
+    pub(crate) run: LogLinearHistogram,
+    pub(crate) wait: LogLinearHistogram,
+    pub(crate) total: LogLinearHistogram, // run + wait
 }
 
 impl JobLatencyCollection {
-    // mutable self so the collections may be sorted
-    fn write_stats(&mut self, job_name: &str, out_file: &mut std::fs::File) {
-        assert!(self.run.len() == self.wait.len());
+    fn new(sub_bucket_bits: u32) -> Self {
+        JobLatencyCollection {
+            run: LogLinearHistogram::for_recording(sub_bucket_bits),
+            wait: LogLinearHistogram::for_recording(sub_bucket_bits),
+            total: LogLinearHistogram::for_recording(sub_bucket_bits),
+        }
+    }
+
+    fn push(&mut self, latency: &JobLatency) {
+        self.run.record(latency.run);
+        self.wait.record(latency.wait);
+        self.total.record(latency.run + latency.wait);
+    }
+
+    // Both collections were made with the same sub-bucket bits
+    fn merge(&mut self, other: &JobLatencyCollection) {
+        self.run.merge(&other.run).unwrap();
+        self.wait.merge(&other.wait).unwrap();
+        self.total.merge(&other.total).unwrap();
+    }
 
-        if self.run.is_empty() {
+    // the run, wait and total histograms, with their names
+    pub(crate) fn latencies(&self) -> [(&'static str, &LogLinearHistogram); 3] {
+        [
+            ("run", &self.run),
+            ("wait", &self.wait),
+            ("total", &self.total),
+        ]
+    }
+
+    fn write_stats(&self, job_name: &str, out_file: &mut std::fs::File, sub_bucket_bits: u32) {
+        if self.run.count() == 0 {
             return;
         }
 
-        let write_histogram = |out_file: &mut std::fs::File, v: &Vec<u64>| {
-            // number of starts printed at the bin
-            // with the max value
-            let histogram_max_stars = 32;
-
-            // histogram countains counts of values less than or equal to the bin
-            // boundary. The boundary is (1 << (bin_index -1)), except for the 0th index,
-            // which is for zeros.
-            let mut h: [i32; 64] = [0; 64];
-            let mut max_bin_index: u64 = 0;
-            for d in v {
-                let bin: usize = if *d == 0 {
-                    0
-                } else {
-                    65 - (d - 1).leading_zeros()
-                } as usize;
-                h[bin] += 1;
-                max_bin_index = max_bin_index.max(bin as u64);
+        writeln!(
+            out_file,
+            "Job: {}: Max Run: {} Max Wait: {} Ave Run: {:.2} Ave Wait: {:.2}",
+            job_name,
+            self.run.max(),
+            self.wait.max(),
+            self.run.mean(),
+            self.wait.mean()
+        )
+        .unwrap();
+        writeln!(out_file, "Samples: {}", self.run.count()).unwrap();
+        write_summary_table(
+            out_file,
+            &[
+                ("Run", &self.run),
+                ("Wait", &self.wait),
+                ("Total", &self.total),
+            ],
+        );
+        writeln!(out_file).unwrap();
+        writeln!(out_file, "Run histogram:").unwrap();
+        self.run
+            .with_sub_bucket_bits(sub_bucket_bits)
+            .write_ascii(out_file);
+        writeln!(out_file).unwrap();
+        writeln!(out_file, "Wait histogram:").unwrap();
+        self.wait
+            .with_sub_bucket_bits(sub_bucket_bits)
+            .write_ascii(out_file);
+        write!(out_file, "\n\n").unwrap();
+    }
+
+    // For example:
+    // {
+    //   "job": "InboundLedger",
+    //   "count": 52,
+    //   "run": {"min": 0, "p50": 33, ..., "buckets": [{"low": 0, "high": 0, "count": 5}, ...]},
+    //   "wait": {...},
+    //   "total": {...}
+    // }
+    fn json_stats(&self, job_name: &str, sub_bucket_bits: u32) -> serde_json::Value {
+        let mut m = serde_json::Map::new();
+        m.insert("job".to_string(), serde_json::json!(job_name));
+        m.insert("count".to_string(), serde_json::json!(self.run.count()));
+        for (name, h) in self.latencies() {
+            m.insert(name.to_string(), summary_json(h, sub_bucket_bits));
+        }
+        serde_json::Value::Object(m)
+    }
+
+    // One row per statistic: job,latency,statistic,value
+    fn write_csv(&self, job_name: &str, out_file: &mut std::fs::File, sub_bucket_bits: u32) {
+        for (name, h) in self.latencies() {
+            let key = format!("{},{}", csv_quote(job_name), name);
+            write_summary_csv(out_file, &key, h, sub_bucket_bits);
+        }
+    }
+}
+
+// Collects the "Job latency" lines one at a time, so the lines don't all have
+// to be in memory at once
+pub struct JobLatencyStats {
+    pub(crate) stats: HashMap<String, JobLatencyCollection>,
+    errors: Vec<String>,
+    sub_bucket_bits: u32, // resolution of the histograms, see `LogLinearHistogram`
+}
+
+impl JobLatencyStats {
+    pub fn new(sub_bucket_bits: u32) -> Self {
+        JobLatencyStats {
+            stats: HashMap::new(),
+            errors: Vec::new(),
+            sub_bucket_bits,
+        }
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        match JobLatency::from_log_line(l) {
+            Ok(Some(latency)) => {
+                let sub_bucket_bits = self.sub_bucket_bits;
+                self.stats
+                    .entry(latency.job.clone())
+                    .or_insert_with(|| JobLatencyCollection::new(sub_bucket_bits))
+                    .push(&latency)
             }
+            Ok(None) => (),
+            Err(()) => self.errors.push(format!("{:?}", l)),
+        }
+    }
 
-            let max_count = h.iter().max().unwrap();
-            let mut any_written = false;
-            for (index, count) in h.iter().enumerate() {
-                if *count == 0 && !any_written {
-                    continue;
+    // Add the stats of other lines, for example of another node, that were
+    // collected with the same sub-bucket bits
+    pub fn merge(&mut self, other: JobLatencyStats) {
+        assert_eq!(self.sub_bucket_bits, other.sub_bucket_bits);
+        for (job, c) in other.stats {
+            match self.stats.get_mut(&job) {
+                Some(existing) => existing.merge(&c),
+                None => {
+                    self.stats.insert(job, c);
                 }
-                let bin_index: u64 = if index == 0 { 0 } else { 1u64 << (index - 1) };
-                write!(out_file, "{:>6} : {:<6} ", bin_index, count).unwrap();
-                let num_stars = count * histogram_max_stars / max_count;
-                for _ in 0..num_stars {
-                    write!(out_file, "*").unwrap();
+            }
+        }
+        self.errors.extend(other.errors);
+    }
+
+    // mutable self so the errors are only reported once
+    pub fn write(&mut self, out_file_name: &std::path::PathBuf, format: ReportFormat) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in job_latency_stats",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        // sort by job name so reports can be compared
+        let sub_bucket_bits = self.sub_bucket_bits;
+        let mut jobs: Vec<(&String, &JobLatencyCollection)> = self.stats.iter().collect();
+        jobs.sort_by(|a, b| a.0.cmp(b.0));
+        match format {
+            ReportFormat::Text => {
+                for (k, v) in jobs {
+                    v.write_stats(k, &mut out_file, sub_bucket_bits);
                 }
-                write!(out_file, "\n").unwrap();
-                any_written = true;
-                if (index as u64) == max_bin_index {
-                    break;
+            }
+            ReportFormat::Json => {
+                let jobs: Vec<serde_json::Value> = jobs
+                    .into_iter()
+                    .map(|(k, v)| v.json_stats(k, sub_bucket_bits))
+                    .collect();
+                writeln!(
+                    out_file,
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({ "jobs": jobs })).unwrap()
+                )
+                .unwrap();
+            }
+            ReportFormat::Csv => {
+                writeln!(out_file, "job,latency,statistic,value").unwrap();
+                for (k, v) in jobs {
+                    v.write_csv(k, &mut out_file, sub_bucket_bits);
                 }
             }
-        };
+        }
 
-        self.run.sort();
-        self.wait.sort();
-        let run_max = self.run[self.run.len() - 1];
-        let run_ave = (Iterator::sum::<u64>(self.run.iter()) as f64) / (self.run.len() as f64);
-        let wait_max = self.wait[self.wait.len() - 1];
-        let wait_ave = (Iterator::sum::<u64>(self.wait.iter()) as f64) / (self.wait.len() as f64);
-        write!(
-            out_file,
-            "Job: {}: Max Run: {} Max Wait: {} Ave Run: {:.2} Ave Wait: {:.2}\n",
-            job_name, run_max, wait_max, run_ave, wait_ave
-        )
-        .unwrap();
-        write!(out_file, "Run histogram:\n").unwrap();
-        write_histogram(out_file, &self.run);
-        write!(out_file, "\n").unwrap();
-        write!(out_file, "Wait histogram:\n").unwrap();
-        write_histogram(out_file, &self.wait);
-        write!(out_file, "\n\n").unwrap();
+        if !self.errors.is_empty() {
+            eprintln!("Error: Invalid job latency lines >>>> ");
+            for e in &self.errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("End Invalid job latency lines <<<< ");
+        }
+        self.errors.clear();
     }
 }
 
-// the `onlyDataAsJson` parameter controls if the whole log line will be written
-// as json (better for computers) or just the json data (more readable for humans)
-pub fn job_latency_stats(log_lines: &Vec<LogLine>, out_file_name: &std::path::PathBuf) {
-    let mut out_file = match std::fs::File::create(out_file_name) {
-        Ok(file) => file,
-        _ => {
-            eprintln!(
-                "Could not create file {} in to_json",
-                out_file_name.display()
-            );
-            std::process::exit(1);
+// Format a time in ms since the epoch like the log timestamps
+pub(crate) fn format_ms(ms: i64) -> String {
+    let nanos = (ms.rem_euclid(1000) * 1_000_000) as u32;
+    match chrono::NaiveDateTime::from_timestamp_opt(ms.div_euclid(1000), nanos) {
+        Some(t) => t.format(TIMESTAMP_FORMAT).to_string(),
+        None => ms.to_string(),
+    }
+}
+
+// The run and wait latencies of a job in one interval. Intervals hold few
+// samples, so they are kept as is rather than in histograms.
+#[derive(Default)]
+struct IntervalLatencies {
+    run: Vec<u64>,
+    wait: Vec<u64>,
+}
+
+// Job latency stats over fixed intervals of time, so latency spikes can be
+// lined up with other events in the log. A "Job latency" line is put in the
+// interval that contains its timestamp. Lines with invalid data are skipped;
+// they are reported by `JobLatencyStats`.
+pub struct JobLatencySeries {
+    interval_ms: i64,
+    // keyed by the start of the interval (ms since the epoch) and the job
+    buckets: BTreeMap<(i64, String), IntervalLatencies>,
+}
+
+impl JobLatencySeries {
+    pub fn new(interval: chrono::Duration) -> Self {
+        JobLatencySeries {
+            interval_ms: interval.num_milliseconds().max(1),
+            buckets: BTreeMap::new(),
         }
-    };
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        if let Ok(Some(latency)) = JobLatency::from_log_line(l) {
+            let ms = l.time.timestamp_millis();
+            let start = ms - ms.rem_euclid(self.interval_ms);
+            let v = self.buckets.entry((start, latency.job)).or_default();
+            v.run.push(latency.run);
+            v.wait.push(latency.wait);
+        }
+    }
 
-    let mut errors = Vec::with_capacity(1024);
+    // mutable self so the collections may be sorted
+    pub fn write(&mut self, out_file_name: &std::path::PathBuf, format: ReportFormat) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in job_latency_series",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
 
-    let mut stats = HashMap::<String, JobLatencyCollection>::new();
+        // start, job, count, run max, run p99, wait max, wait p99
+        let rows = self.buckets.iter_mut().map(|((start, job), v)| {
+            v.run.sort();
+            v.wait.sort();
+            (
+                format_ms(*start),
+                job.as_str(),
+                v.run.len(),
+                v.run[v.run.len() - 1],
+                percentile(&v.run, 99.0),
+                v.wait[v.wait.len() - 1],
+                percentile(&v.wait, 99.0),
+            )
+        });
 
-    for l in log_lines {
-        if l.msg != "Job latency" {
-            continue;
+        match format {
+            ReportFormat::Text => {
+                writeln!(
+                    out_file,
+                    "{:<34} {:<24} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                    "Start", "Job", "Count", "Run Max", "Run p99", "Wait Max", "Wait p99"
+                )
+                .unwrap();
+                for (start, job, count, run_max, run_p99, wait_max, wait_p99) in rows {
+                    writeln!(
+                        out_file,
+                        "{:<34} {:<24} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                        start, job, count, run_max, run_p99, wait_max, wait_p99
+                    )
+                    .unwrap();
+                }
+            }
+            ReportFormat::Json => {
+                let intervals: Vec<serde_json::Value> = rows
+                    .map(
+                        |(start, job, count, run_max, run_p99, wait_max, wait_p99)| {
+                            serde_json::json!({
+                                "start": start,
+                                "job": job,
+                                "count": count,
+                                "run": {"max": run_max, "p99": run_p99},
+                                "wait": {"max": wait_max, "p99": wait_p99},
+                            })
+                        },
+                    )
+                    .collect();
+                let v = serde_json::json!({
+                    "interval_ms": self.interval_ms,
+                    "intervals": intervals,
+                });
+                writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+            }
+            ReportFormat::Csv => {
+                writeln!(
+                    out_file,
+                    "start,job,count,run_max,run_p99,wait_max,wait_p99"
+                )
+                .unwrap();
+                for (start, job, count, run_max, run_p99, wait_max, wait_p99) in rows {
+                    writeln!(
+                        out_file,
+                        "{},{},{},{},{},{},{}",
+                        start,
+                        csv_quote(job),
+                        count,
+                        run_max,
+                        run_p99,
+                        wait_max,
+                        wait_p99
+                    )
+                    .unwrap();
+                }
+            }
         }
-        let jdata = l.data_to_json_value();
-        if jdata.is_none() {
-            errors.push(l);
-            continue;
+    }
+}
+
+pub fn job_latency_stats(
+    log_lines: &Vec<LogLine>,
+    out_file_name: &std::path::PathBuf,
+    format: ReportFormat,
+    sub_bucket_bits: u32,
+) {
+    let mut stats = JobLatencyStats::new(sub_bucket_bits);
+    for l in log_lines {
+        stats.add(l);
+    }
+    stats.write(out_file_name, format);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_is_the_nearest_rank() {
+        let v: Vec<u64> = (1..=10).collect();
+        // p0 and small percentiles are the first value
+        assert_eq!(percentile(&v, 0.0), 1);
+        assert_eq!(percentile(&v, 1.0), 1);
+        assert_eq!(percentile(&v, 100.0), 10);
+        // rank 5 exactly, and just past it
+        assert_eq!(percentile(&v, 50.0), 5);
+        assert_eq!(percentile(&v, 50.1), 6);
+        assert_eq!(percentile(&v, 90.0), 9);
+        assert_eq!(percentile(&v, 99.0), 10);
+        for p in [0.0, 50.0, 99.9, 100.0] {
+            assert_eq!(percentile(&[7], p), 7);
         }
-        let latency = JobLatency::from_json_value(jdata.unwrap());
-        if latency.is_none() {
-            errors.push(l);
-            continue;
+    }
+
+    #[test]
+    fn legacy_messages() {
+        let latency =
+            JobLatency::from_legacy_msg("Job: processLedgerData run: 2ms wait: 1455ms").unwrap();
+        assert_eq!(
+            (latency.job.as_str(), latency.run, latency.wait),
+            ("processLedgerData", 2, 1455)
+        );
+        for msg in [
+            "Job: processLedgerData run: 2ms",
+            "Job: processLedgerData run: 2 wait: 1455",
+            "Job: processLedgerData run: -2ms wait: 1455ms",
+            "Job: processLedgerData run: 2ms wait: 1455ms extra",
+            "Job: run: 2ms wait: 1455ms",
+            "Job: processLedgerData run: 99999999999999999999ms wait: 1ms",
+        ] {
+            assert!(JobLatency::from_legacy_msg(msg).is_none(), "{}", msg);
         }
-        let latency = latency.unwrap();
+    }
 
-        if let Some(v) = stats.get_mut(&latency.job) {
-            v.run.push(latency.run);
-            v.wait.push(latency.wait);
-        } else {
-            stats.insert(
-                latency.job,
-                JobLatencyCollection {
-                    run: Vec::with_capacity(32),
-                    wait: Vec::with_capacity(32),
-                },
+    #[test]
+    fn malformed_lines_of_both_formats_are_invalid() {
+        let lines = [
+            "2021-Feb-12 17:00:00.000000000 UTC LoadMonitor:WRN Job: processLedgerData run: 2ms",
+            "2021-Feb-12 17:00:00.000000000 UTC LoadMonitor:WRN Job latency {\"job\": \"A\", \"run(ms)\": \"x\"}",
+        ];
+        for l in lines {
+            assert!(
+                JobLatency::from_log_line(&LogLine::new(l).unwrap()).is_err(),
+                "{}",
+                l
             );
         }
+        let other = "2021-Feb-12 17:00:00.000000000 UTC LoadMonitor:WRN Jobs: 3";
+        assert!(matches!(
+            JobLatency::from_log_line(&LogLine::new(other).unwrap()),
+            Ok(None)
+        ));
     }
 
-    for (k, v) in &mut stats {
-        v.write_stats(&k, &mut out_file);
+    fn latency_line(time: &str, job: &str, run: u64, wait: u64) -> String {
+        format!(
+            "2021-Feb-12 17:{} UTC LoadMonitor:WRN Job latency {{\"job\": \"{}\", \"run(ms)\": {}, \"wait(ms)\": {}, \"jlogId\": 1}}",
+            time, job, run, wait
+        )
+    }
+
+    #[test]
+    fn series_puts_lines_in_the_interval_of_their_time() {
+        let lines = [
+            latency_line("00:10.000000000", "A", 5, 100),
+            latency_line("00:59.999999999", "A", 7, 300),
+            latency_line("00:30.000000000", "B", 1, 2),
+            latency_line("01:00.000000000", "A", 2, 1),
+            "2021-Feb-12 17:00:20.000000000 UTC Peer:NFO not a job".to_string(),
+        ];
+        let mut series = JobLatencySeries::new(chrono::Duration::minutes(1));
+        for l in &lines {
+            series.add(&LogLine::new(l).unwrap());
+        }
+        let path = std::env::temp_dir().join(format!("series-{}.csv", std::process::id()));
+        series.write(&path, ReportFormat::Csv);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let start = |minute: &str| format!("2021-Feb-12 17:{}:00 UTC", minute);
+        assert_eq!(
+            csv.lines().collect::<Vec<&str>>(),
+            vec![
+                "start,job,count,run_max,run_p99,wait_max,wait_p99".to_string(),
+                format!("{},\"A\",2,7,7,300,300", start("00")),
+                format!("{},\"B\",1,1,1,2,2", start("00")),
+                format!("{},\"A\",1,2,2,1,1", start("01")),
+            ]
+        );
     }
 
-    if !errors.is_empty() {
-        eprintln!("Error: Invalid json data >>>> ");
-        for e in &errors {
-            eprintln!("{:?}", e);
+    #[test]
+    fn merged_stats_match_the_stats_of_all_lines() {
+        let lines = [
+            latency_line("00:10.000000000", "A", 5, 100),
+            latency_line("00:20.000000000", "A", 7000, 300),
+            latency_line("00:30.000000000", "B", 1, 2),
+            latency_line("00:40.000000000", "A", 2, 1),
+        ];
+        let mut all = JobLatencyStats::new(3);
+        let (mut first, mut second) = (JobLatencyStats::new(3), JobLatencyStats::new(3));
+        for (i, l) in lines.iter().enumerate() {
+            let l = LogLine::new(l).unwrap();
+            all.add(&l);
+            if i < 2 {
+                first.add(&l)
+            } else {
+                second.add(&l)
+            }
+        }
+        first.merge(second);
+        assert_eq!(first.stats.len(), 2);
+        for (job, c) in &all.stats {
+            assert_eq!(first.stats[job].latencies(), c.latencies(), "{}", job);
         }
-        eprintln!("End Invalid json data <<<< ");
+    }
+
+    #[test]
+    fn csv_job_names_are_quoted() {
+        let l = "2021-Feb-12 17:00:00.000000000 UTC LoadMonitor:WRN Job latency {\"job\": \"a, \\\"b\\\"\", \"run(ms)\": 3, \"wait(ms)\": 1, \"jlogId\": 1}";
+        let mut stats = JobLatencyStats::new(3);
+        stats.add(&LogLine::new(l).unwrap());
+        let path = std::env::temp_dir().join(format!("latency-{}.csv", std::process::id()));
+        stats.write(&path, ReportFormat::Csv);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(csv.lines().any(|r| r == "\"a, \"\"b\"\"\",run,max,3"));
     }
 }
@@ -0,0 +1,404 @@
+// Compare the job latencies of a baseline log and a candidate log
+
+// For every job, and for the run, wait and total latencies, the diff reports
+// the baseline and candidate values of the count, mean and percentiles, and the
+// change relative to the baseline. The two sample Kolmogorov-Smirnov statistic
+// (the largest distance between the two cumulative distributions, from 0 for
+// the same distribution to 1 for distributions that don't overlap) measures how
+// much the shape of the distribution shifted.
+//
+// A job regressed if the mean or p99 of any of its latencies grew by more than
+// the tolerance. Changes are relative to the baseline, and a baseline of 0ms is
+// treated as 1ms so a change is always defined.
+//
+// The latencies are recorded into histograms (see `JobLatencyStats`), so the
+// percentiles and the Kolmogorov-Smirnov statistic are computed over the
+// buckets of the histograms.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::job_latency::{JobLatencyCollection, JobLatencyStats, Summary};
+use crate::log_linear_histogram::LogLinearHistogram;
+use crate::report_format::{csv_quote, ReportFormat};
+
+// Statistics that are checked for regressions
+const CHECKED: [&str; 2] = ["mean", "p99"];
+
+// Two sample Kolmogorov-Smirnov statistic of two non-empty histograms with the
+// same sub-bucket bits, at the end of every bucket
+fn ks_statistic(a: &LogLinearHistogram, b: &LogLinearHistogram) -> f64 {
+    let (na, nb) = (a.count() as f64, b.count() as f64);
+    let (ca, cb) = (a.counts(), b.counts());
+    let (mut sa, mut sb) = (0, 0);
+    let mut d: f64 = 0.0;
+    for i in 0..ca.len().max(cb.len()) {
+        sa += ca.get(i).copied().unwrap_or(0);
+        sb += cb.get(i).copied().unwrap_or(0);
+        d = d.max((sa as f64 / na - sb as f64 / nb).abs());
+    }
+    d
+}
+
+fn change_percent(baseline: f64, candidate: f64) -> f64 {
+    (candidate - baseline) / baseline.max(1.0) * 100.0
+}
+
+// The statistics of one non-empty latency histogram, as (name, value)
+fn side_statistics(h: &LogLinearHistogram) -> Vec<(&'static str, f64)> {
+    let s = Summary::from_histogram(h);
+    vec![
+        ("count", h.count() as f64),
+        ("mean", s.mean),
+        ("p50", s.p50 as f64),
+        ("p90", s.p90 as f64),
+        ("p99", s.p99 as f64),
+        ("p99.9", s.p999 as f64),
+        ("max", s.max as f64),
+    ]
+}
+
+// The statistics of one latency of one job, as (name, baseline, candidate)
+fn statistics(
+    baseline: &LogLinearHistogram,
+    candidate: &LogLinearHistogram,
+) -> Vec<(&'static str, f64, f64)> {
+    side_statistics(baseline)
+        .into_iter()
+        .zip(side_statistics(candidate))
+        .map(|((name, b), (_, c))| (name, b, c))
+        .collect()
+}
+
+struct Row {
+    latency: &'static str,
+    statistic: &'static str,
+    baseline: f64,
+    candidate: f64,
+    change: Option<f64>, // percent, not defined for the KS statistic
+    regressed: bool,
+}
+
+// The rows of the diff of one job
+fn job_rows(
+    baseline: &JobLatencyCollection,
+    candidate: &JobLatencyCollection,
+    tolerance: f64,
+) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for ((latency, b), (_, c)) in baseline
+        .latencies()
+        .iter()
+        .zip(candidate.latencies().iter())
+    {
+        for (statistic, bv, cv) in statistics(b, c) {
+            let change = change_percent(bv, cv);
+            rows.push(Row {
+                latency,
+                statistic,
+                baseline: bv,
+                candidate: cv,
+                change: Some(change),
+                regressed: CHECKED.contains(&statistic) && change > tolerance,
+            });
+        }
+        rows.push(Row {
+            latency,
+            statistic: "ks",
+            baseline: 0.0,
+            candidate: ks_statistic(b, c),
+            change: Option::None,
+            regressed: false,
+        });
+    }
+    rows
+}
+
+fn format_value(statistic: &str, v: f64) -> String {
+    match statistic {
+        "mean" => format!("{:.2}", v),
+        "ks" => format!("{:.3}", v),
+        _ => format!("{}", v),
+    }
+}
+
+// Write the csv rows of a job that has samples in only one of the logs, with
+// empty values for the other log
+fn write_one_side_csv<W: Write>(
+    out: &mut W,
+    job: &str,
+    only_in: &str,
+    collection: &JobLatencyCollection,
+) {
+    if collection.run.count() == 0 {
+        return;
+    }
+    for (latency, h) in collection.latencies().iter() {
+        for (statistic, v) in side_statistics(h) {
+            let v = format_value(statistic, v);
+            let (baseline, candidate) = if only_in == "baseline" {
+                (v.as_str(), "")
+            } else {
+                ("", v.as_str())
+            };
+            writeln!(
+                out,
+                "{},{},{},{},{},,false",
+                csv_quote(job),
+                latency,
+                statistic,
+                baseline,
+                candidate
+            )
+            .unwrap();
+        }
+    }
+}
+
+// Write the diff of the baseline and candidate job latencies. `tolerance` is
+// in percent.
+pub fn write_diff(
+    baseline: &JobLatencyStats,
+    candidate: &JobLatencyStats,
+    out_file_name: &std::path::PathBuf,
+    format: ReportFormat,
+    tolerance: f64,
+) {
+    let mut out_file = match std::fs::File::create(out_file_name) {
+        Ok(file) => file,
+        _ => {
+            eprintln!(
+                "Could not create file {} in job_latency_diff",
+                out_file_name.display()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let jobs: BTreeSet<&String> = baseline
+        .stats
+        .keys()
+        .chain(candidate.stats.keys())
+        .collect();
+
+    let mut regressed = Vec::new();
+    let mut json_jobs = Vec::new();
+    if format == ReportFormat::Csv {
+        writeln!(
+            out_file,
+            "job,latency,statistic,baseline,candidate,change_percent,regressed"
+        )
+        .unwrap();
+    }
+
+    for job in jobs {
+        let (b, c) = match (baseline.stats.get(job), candidate.stats.get(job)) {
+            (Some(b), Some(c)) if b.run.count() != 0 && c.run.count() != 0 => (b, c),
+            (b, c) => {
+                let only_in = if b.is_some() { "baseline" } else { "candidate" };
+                match format {
+                    ReportFormat::Text => {
+                        writeln!(out_file, "Job: {}: only in the {}\n", job, only_in).unwrap()
+                    }
+                    ReportFormat::Json => json_jobs.push(serde_json::json!({
+                        "job": job,
+                        "only_in": only_in,
+                    })),
+                    ReportFormat::Csv => {
+                        let collection = if b.is_some() { b } else { c };
+                        write_one_side_csv(&mut out_file, job, only_in, collection.unwrap())
+                    }
+                }
+                continue;
+            }
+        };
+
+        let rows = job_rows(b, c, tolerance);
+        let job_regressed = rows.iter().any(|r| r.regressed);
+        if job_regressed {
+            regressed.push(job.clone());
+        }
+
+        match format {
+            ReportFormat::Text => {
+                writeln!(
+                    out_file,
+                    "Job: {}{}",
+                    job,
+                    if job_regressed { ": REGRESSED" } else { "" }
+                )
+                .unwrap();
+                writeln!(
+                    out_file,
+                    "{:<6} {:<10} {:>10} {:>10} {:>10}",
+                    "", "Statistic", "Baseline", "Candidate", "Change"
+                )
+                .unwrap();
+                for r in &rows {
+                    let change = match r.change {
+                        Some(change) => format!("{:+.2}%", change),
+                        None => String::new(),
+                    };
+                    let baseline = match r.change {
+                        Some(_) => format_value(r.statistic, r.baseline),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        out_file,
+                        "{:<6} {:<10} {:>10} {:>10} {:>10}{}",
+                        r.latency,
+                        r.statistic,
+                        baseline,
+                        format_value(r.statistic, r.candidate),
+                        change,
+                        if r.regressed { " *" } else { "" }
+                    )
+                    .unwrap();
+                }
+                writeln!(out_file).unwrap();
+            }
+            ReportFormat::Json => {
+                let mut m = serde_json::Map::new();
+                m.insert("job".to_string(), serde_json::json!(job));
+                m.insert("regressed".to_string(), serde_json::json!(job_regressed));
+                for r in &rows {
+                    let latency = m
+                        .entry(r.latency)
+                        .or_insert_with(|| serde_json::json!({}))
+                        .as_object_mut()
+                        .unwrap();
+                    let v = match r.change {
+                        Some(change) => serde_json::json!({
+                            "baseline": r.baseline,
+                            "candidate": r.candidate,
+                            "change_percent": change,
+                            "regressed": r.regressed,
+                        }),
+                        None => serde_json::json!(r.candidate),
+                    };
+                    latency.insert(r.statistic.to_string(), v);
+                }
+                json_jobs.push(serde_json::Value::Object(m));
+            }
+            ReportFormat::Csv => {
+                for r in &rows {
+                    let (baseline, change) = match r.change {
+                        Some(change) => (
+                            format_value(r.statistic, r.baseline),
+                            format!("{:.2}", change),
+                        ),
+                        None => (String::new(), String::new()),
+                    };
+                    writeln!(
+                        out_file,
+                        "{},{},{},{},{},{},{}",
+                        csv_quote(job),
+                        r.latency,
+                        r.statistic,
+                        baseline,
+                        format_value(r.statistic, r.candidate),
+                        change,
+                        r.regressed
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+
+    match format {
+        ReportFormat::Text => writeln!(
+            out_file,
+            "Regressed jobs (mean or p99 over {}%): {}",
+            tolerance,
+            if regressed.is_empty() {
+                "none".to_string()
+            } else {
+                regressed.join(", ")
+            }
+        )
+        .unwrap(),
+        ReportFormat::Json => {
+            let v = serde_json::json!({
+                "tolerance_percent": tolerance,
+                "regressed": regressed,
+                "jobs": json_jobs,
+            });
+            writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+        }
+        ReportFormat::Csv => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_linear_histogram::RECORDING_SUB_BUCKET_BITS;
+
+    fn h(values: &[u64]) -> LogLinearHistogram {
+        LogLinearHistogram::from_values(RECORDING_SUB_BUCKET_BITS, values)
+    }
+
+    #[test]
+    fn ks_statistic_of_same_and_disjoint_distributions() {
+        assert_eq!(ks_statistic(&h(&[1, 2, 3]), &h(&[1, 2, 3])), 0.0);
+        assert_eq!(ks_statistic(&h(&[1, 1, 2, 2]), &h(&[1, 2])), 0.0);
+        assert_eq!(ks_statistic(&h(&[1, 2, 3]), &h(&[4, 5])), 1.0);
+        assert_eq!(ks_statistic(&h(&[4, 5]), &h(&[1, 2, 3])), 1.0);
+    }
+
+    #[test]
+    fn ks_statistic_of_overlapping_distributions() {
+        // after 2: a is at 2/4, b at 0/2
+        assert_eq!(ks_statistic(&h(&[1, 2, 3, 4]), &h(&[3, 4])), 0.5);
+        // ties are stepped over together: after 5, a is at 3/3 and b at 1/2
+        assert_eq!(ks_statistic(&h(&[5, 5, 5]), &h(&[5, 6])), 0.5);
+        let d = ks_statistic(&h(&[1, 2, 3]), &h(&[2, 3, 4]));
+        assert!((d - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ks_statistic_steps_over_each_bucket() {
+        // 1000 and 1001 share a bucket, so they count as the same latency
+        assert_eq!(ks_statistic(&h(&[1000]), &h(&[1001])), 0.0);
+        assert_eq!(ks_statistic(&h(&[1000]), &h(&[1100])), 1.0);
+    }
+
+    #[test]
+    fn change_percent_is_relative_to_the_baseline() {
+        assert_eq!(change_percent(100.0, 150.0), 50.0);
+        assert_eq!(change_percent(200.0, 100.0), -50.0);
+        assert_eq!(change_percent(7.0, 7.0), 0.0);
+        // a baseline of 0ms is treated as 1ms
+        assert_eq!(change_percent(0.0, 3.0), 300.0);
+        assert_eq!(change_percent(0.5, 1.5), 100.0);
+    }
+
+    fn stats(lines: &[(&str, u64, u64)]) -> JobLatencyStats {
+        let mut stats = JobLatencyStats::new(3);
+        for (job, run, wait) in lines {
+            let l = format!(
+                "2021-Feb-12 17:00:00.000000000 UTC LoadMonitor:WRN Job latency {{\"job\": \"{}\", \"run(ms)\": {}, \"wait(ms)\": {}, \"jlogId\": 1}}",
+                job, run, wait
+            );
+            stats.add(&crate::log_line::LogLine::new(&l).unwrap());
+        }
+        stats
+    }
+
+    #[test]
+    fn csv_has_jobs_that_are_only_in_one_log() {
+        let baseline = stats(&[("both", 10, 1), ("old", 5, 0)]);
+        let candidate = stats(&[("both", 12, 1), ("new", 7, 2)]);
+        let path = std::env::temp_dir().join(format!("latency-diff-{}.csv", std::process::id()));
+        write_diff(&baseline, &candidate, &path, ReportFormat::Csv, 10.0);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert!(rows.contains(&"\"both\",run,mean,10.00,12.00,20.00,true"));
+        assert!(rows.contains(&"\"old\",run,max,5,,,false"));
+        assert!(rows.contains(&"\"new\",total,count,,1,,false"));
+        // header, 3 latencies of 8 rows for "both", and of 7 rows for the others
+        assert_eq!(rows.len(), 1 + 24 + 21 + 21);
+    }
+}
@@ -0,0 +1,265 @@
+// Check job latencies against thresholds
+
+// The thresholds are read from a config file with one rule per line:
+//
+// # job           latency  statistic  op  threshold
+// InboundLedger   wait     p99        <   2000ms
+// *               run      max        <=  1000
+//
+// The latency is `run`, `wait` or `total` (run + wait). The statistic is one of
+// `min`, `p50`, `p90`, `p99`, `p99.9`, `max` or `mean`, and the threshold is in
+// milliseconds. The job `*` applies the rule to every job. Blank lines and
+// lines starting with `#` are ignored.
+//
+// Every rule reports pass or fail, and a failed rule also reports the samples
+// with the largest latencies over the threshold and when they happened. A rule
+// that applies to no samples fails, so a job that was renamed or never ran
+// isn't taken as a pass.
+
+use chrono::NaiveDateTime;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::job_latency::{percentile, JobLatency, Summary};
+use crate::log_line::{LogLine, TIMESTAMP_FORMAT};
+
+// Number of violations reported for every failed rule
+const WORST_VIOLATIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Latency {
+    Run,
+    Wait,
+    Total,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Statistic {
+    Min,
+    Percentile(f64),
+    Max,
+    Mean,
+}
+
+struct Rule {
+    text: String, // the rule as written in the config file
+    job: String,  // `*` for all jobs
+    latency: Latency,
+    statistic: Statistic,
+    inclusive: bool, // `<=` rather than `<`
+    threshold: u64,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 5 {
+            return Err("expected <job> <latency> <statistic> <op> <threshold>".to_string());
+        }
+        let latency = match words[1] {
+            "run" => Latency::Run,
+            "wait" => Latency::Wait,
+            "total" => Latency::Total,
+            s => return Err(format!("bad latency {}, expected run, wait or total", s)),
+        };
+        let statistic = match words[2] {
+            "min" => Statistic::Min,
+            "max" => Statistic::Max,
+            "mean" | "ave" => Statistic::Mean,
+            s => match s.strip_prefix('p').and_then(|p| p.parse::<f64>().ok()) {
+                Some(p) if p > 0.0 && p <= 100.0 => Statistic::Percentile(p),
+                _ => return Err(format!("bad statistic {}", s)),
+            },
+        };
+        let inclusive = match words[3] {
+            "<" => false,
+            "<=" => true,
+            s => return Err(format!("bad operator {}, expected < or <=", s)),
+        };
+        let threshold = words[4]
+            .trim_end_matches("ms")
+            .parse()
+            .map_err(|_| format!("bad threshold {}", words[4]))?;
+        Ok(Rule {
+            text: words.join(" "),
+            job: words[0].to_string(),
+            latency,
+            statistic,
+            inclusive,
+            threshold,
+        })
+    }
+
+    fn passes(&self, value: f64) -> bool {
+        if self.inclusive {
+            value <= self.threshold as f64
+        } else {
+            value < self.threshold as f64
+        }
+    }
+
+    fn applies_to(&self, job: &str) -> bool {
+        self.job == "*" || self.job == job
+    }
+}
+
+#[derive(Default)]
+pub struct JobLatencySlo {
+    rules: Vec<Rule>,
+    // latencies and their times, by job and latency
+    samples: BTreeMap<(String, Latency), Vec<(u64, NaiveDateTime)>>,
+}
+
+impl JobLatencySlo {
+    pub fn new(config_file_name: &std::path::PathBuf) -> Self {
+        let config = match std::fs::read_to_string(config_file_name) {
+            Ok(config) => config,
+            Err(why) => {
+                eprintln!("Couldn't read {}: {}", config_file_name.display(), why);
+                std::process::exit(1);
+            }
+        };
+        let mut rules = Vec::new();
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Rule::parse(line) {
+                Ok(rule) => rules.push(rule),
+                Err(why) => {
+                    eprintln!("{} line {}: {}", config_file_name.display(), i + 1, why);
+                    std::process::exit(1);
+                }
+            }
+        }
+        JobLatencySlo {
+            rules,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        let latency = match JobLatency::from_log_line(l) {
+            Ok(Some(latency)) => latency,
+            _ => return,
+        };
+        for (kind, value) in [
+            (Latency::Run, latency.run),
+            (Latency::Wait, latency.wait),
+            (Latency::Total, latency.run + latency.wait),
+        ] {
+            if !self
+                .rules
+                .iter()
+                .any(|r| r.latency == kind && r.applies_to(&latency.job))
+            {
+                continue;
+            }
+            self.samples
+                .entry((latency.job.clone(), kind))
+                .or_default()
+                .push((value, l.time));
+        }
+    }
+
+    // Check every rule, and write pass or fail for every job it applies to.
+    // Returns true if all the rules pass.
+    pub fn check<W: Write>(&mut self, out: &mut W) -> bool {
+        for samples in self.samples.values_mut() {
+            samples.sort();
+        }
+
+        let mut all_pass = true;
+        for rule in &self.rules {
+            let mut any_samples = false;
+            for ((job, _), samples) in self
+                .samples
+                .iter()
+                .filter(|((job, latency), _)| *latency == rule.latency && rule.applies_to(job))
+            {
+                any_samples = true;
+                let sorted: Vec<u64> = samples.iter().map(|(v, _)| *v).collect();
+                let value = match rule.statistic {
+                    Statistic::Min => sorted[0] as f64,
+                    Statistic::Percentile(p) => percentile(&sorted, p) as f64,
+                    Statistic::Max => sorted[sorted.len() - 1] as f64,
+                    Statistic::Mean => Summary::new(&sorted).mean,
+                };
+                let pass = rule.passes(value);
+                let value = match rule.statistic {
+                    Statistic::Mean => format!("{:.2}", value),
+                    _ => format!("{}", value),
+                };
+                all_pass &= pass;
+                writeln!(
+                    out,
+                    "{} {}: {}: {}ms ({} samples)",
+                    if pass { "PASS" } else { "FAIL" },
+                    rule.text,
+                    job,
+                    value,
+                    sorted.len()
+                )
+                .unwrap();
+                if pass {
+                    continue;
+                }
+                for (v, time) in samples
+                    .iter()
+                    .rev()
+                    .take_while(|(v, _)| !rule.passes(*v as f64))
+                    .take(WORST_VIOLATIONS)
+                {
+                    writeln!(out, "    {} {}ms", time.format(TIMESTAMP_FORMAT), v).unwrap();
+                }
+            }
+            if !any_samples {
+                all_pass = false;
+                writeln!(out, "FAIL {}: no samples", rule.text).unwrap();
+            }
+        }
+        all_pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slo(rules: &[&str], lines: &[&str]) -> JobLatencySlo {
+        let mut slo = JobLatencySlo {
+            rules: rules.iter().map(|r| Rule::parse(r).unwrap()).collect(),
+            samples: BTreeMap::new(),
+        };
+        for l in lines {
+            slo.add(&LogLine::new(l).unwrap());
+        }
+        slo
+    }
+
+    const LINE: &str = "2021-Feb-12 03:00:04.020060136 UTC LoadMonitor:WRN Job latency {\"job\": \"InboundLedger\", \"run(ms)\": 3, \"wait(ms)\": 1366, \"jlogId\": 115}";
+
+    #[test]
+    fn rule_without_samples_fails() {
+        let mut out = Vec::new();
+        assert!(!slo(&["AcceptLedger run max < 10"], &[LINE]).check(&mut out));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "FAIL AcceptLedger run max < 10: no samples\n"
+        );
+    }
+
+    #[test]
+    fn rules_are_checked_against_the_statistic() {
+        let mut out = Vec::new();
+        assert!(slo(
+            &["* run max <= 3", "InboundLedger total p99 < 2000ms"],
+            &[LINE]
+        )
+        .check(&mut out));
+        let mut out = Vec::new();
+        assert!(!slo(&["* run max < 3"], &[LINE]).check(&mut out));
+    }
+}
@@ -0,0 +1,440 @@
+// Reconstruct the depth of the job queue from the job latency lines
+
+// A "Job latency" line is written when a job finishes, so for every job:
+//
+// end     = time of the line
+// start   = end - run
+// enqueue = start - wait
+//
+// Sweeping through these events in time order gives the number of jobs of every
+// type that were waiting and running at every instant. The depth is reported as
+// the max number of waiting and running jobs in every interval of time.
+//
+// The job queue runs a limited number of jobs at once. The most jobs that were
+// ever running at the same time is taken as that limit, and the queue is
+// saturated while that many jobs are running and other jobs are waiting.
+// Times have millisecond resolution, like the run and wait times.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::job_latency::{format_ms, JobLatency};
+use crate::log_line::LogLine;
+use crate::report_format::{csv_quote, ReportFormat};
+
+// Number of saturated periods that are reported
+const MAX_SATURATED_PERIODS: usize = 10;
+
+// All the events at the same time are applied before the depth is looked at,
+// so the order of events at the same instant doesn't matter. A job that ends
+// and a job that starts at the same instant are not counted as running
+// together, and a job with a zero run or wait time never changes the depth.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum EventKind {
+    End,
+    Start,
+    Enqueue,
+}
+
+struct Event {
+    time: i64, // ms since the epoch
+    kind: EventKind,
+    job: usize, // index in `JobQueue::jobs`
+}
+
+// Number of waiting and running jobs
+#[derive(Clone, Copy, Default)]
+struct Depth {
+    waiting: i64,
+    running: i64,
+}
+
+impl Depth {
+    fn apply(&mut self, kind: EventKind) {
+        match kind {
+            EventKind::End => self.running -= 1,
+            EventKind::Start => {
+                self.waiting -= 1;
+                self.running += 1;
+            }
+            EventKind::Enqueue => self.waiting += 1,
+        }
+    }
+
+    fn max(&self, other: &Depth) -> Depth {
+        Depth {
+            waiting: self.waiting.max(other.waiting),
+            running: self.running.max(other.running),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.waiting == 0 && self.running == 0
+    }
+}
+
+struct SaturatedPeriod {
+    start: i64,
+    end: i64,
+    max_waiting: i64,
+}
+
+pub struct JobQueue {
+    interval_ms: i64,
+    jobs: Vec<String>,
+    job_index: HashMap<String, usize>,
+    events: Vec<Event>,
+}
+
+impl JobQueue {
+    pub fn new(interval: chrono::Duration) -> Self {
+        JobQueue {
+            interval_ms: interval.num_milliseconds().max(1),
+            jobs: Vec::new(),
+            job_index: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, l: &LogLine) {
+        let latency = match JobLatency::from_log_line(l) {
+            Ok(Some(latency)) => latency,
+            _ => return,
+        };
+        let jobs = &mut self.jobs;
+        let job = *self
+            .job_index
+            .entry(latency.job.clone())
+            .or_insert_with(|| {
+                jobs.push(latency.job.clone());
+                jobs.len() - 1
+            });
+        let end = l.time.timestamp_millis();
+        let start = end - latency.run as i64;
+        let enqueue = start - latency.wait as i64;
+        for (time, kind) in [
+            (end, EventKind::End),
+            (start, EventKind::Start),
+            (enqueue, EventKind::Enqueue),
+        ] {
+            self.events.push(Event { time, kind, job });
+        }
+    }
+
+    // The max depth of every job type in every interval, as (interval start,
+    // job, depth). The job `None` is all the jobs together.
+    fn interval_depths(&self) -> Vec<(i64, Option<usize>, Depth)> {
+        let mut rows = Vec::new();
+        if self.events.is_empty() {
+            return rows;
+        }
+        let n = self.jobs.len();
+        // the last element is all the jobs together
+        let mut depth = vec![Depth::default(); n + 1];
+        let mut max_depth = depth.clone();
+        let interval_start = |t: i64| t - t.rem_euclid(self.interval_ms);
+        let mut bucket = interval_start(self.events[0].time);
+
+        let flush = |bucket: i64, max_depth: &[Depth], rows: &mut Vec<_>| {
+            for (i, d) in max_depth.iter().enumerate() {
+                if !d.is_empty() {
+                    rows.push((bucket, if i < n { Some(i) } else { None }, *d));
+                }
+            }
+        };
+
+        for (i, e) in self.events.iter().enumerate() {
+            let b = interval_start(e.time);
+            if b != bucket {
+                flush(bucket, &max_depth, &mut rows);
+                max_depth.clone_from(&depth);
+                // the depth carries over intervals without any events
+                if depth.iter().any(|d| !d.is_empty()) {
+                    let mut skipped = bucket + self.interval_ms;
+                    while skipped < b {
+                        flush(skipped, &depth, &mut rows);
+                        skipped += self.interval_ms;
+                    }
+                }
+                bucket = b;
+            }
+            depth[e.job].apply(e.kind);
+            depth[n].apply(e.kind);
+            if self.is_last_at_time(i) {
+                for (m, d) in max_depth.iter_mut().zip(&depth) {
+                    *m = m.max(d);
+                }
+            }
+        }
+        flush(bucket, &max_depth, &mut rows);
+        rows
+    }
+
+    // Return true if the event at `i` is the last one at its time
+    fn is_last_at_time(&self, i: usize) -> bool {
+        self.events
+            .get(i + 1)
+            .is_none_or(|next| next.time != self.events[i].time)
+    }
+
+    // The most jobs that ran at once, and the periods when that many jobs were
+    // running while other jobs waited, longest first
+    fn saturated_periods(&self) -> (i64, Vec<SaturatedPeriod>) {
+        let mut depth = Depth::default();
+        let mut peak_running = 0;
+        for (i, e) in self.events.iter().enumerate() {
+            depth.apply(e.kind);
+            if self.is_last_at_time(i) {
+                peak_running = peak_running.max(depth.running);
+            }
+        }
+
+        let mut periods = Vec::new();
+        let mut current: Option<SaturatedPeriod> = Option::None;
+        let mut depth = Depth::default();
+        for (i, e) in self.events.iter().enumerate() {
+            depth.apply(e.kind);
+            if !self.is_last_at_time(i) {
+                continue;
+            }
+            let saturated = peak_running > 0 && depth.running >= peak_running && depth.waiting > 0;
+            match (&mut current, saturated) {
+                (Some(p), true) => p.max_waiting = p.max_waiting.max(depth.waiting),
+                (Some(p), false) => {
+                    p.end = e.time;
+                    periods.push(current.take().unwrap());
+                }
+                (None, true) => {
+                    current = Some(SaturatedPeriod {
+                        start: e.time,
+                        end: e.time,
+                        max_waiting: depth.waiting,
+                    })
+                }
+                (None, false) => (),
+            }
+        }
+        // a period that is still open at the last event ends there
+        if let (Some(mut p), Some(last)) = (current, self.events.last()) {
+            p.end = last.time;
+            periods.push(p);
+        }
+        periods.sort_by_key(|p| std::cmp::Reverse(p.end - p.start));
+        periods.truncate(MAX_SATURATED_PERIODS);
+        (peak_running, periods)
+    }
+
+    pub fn write(&mut self, out_file_name: &std::path::PathBuf, format: ReportFormat) {
+        let mut out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in job_queue",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+
+        self.events.sort_by_key(|e| (e.time, e.kind));
+        let mut rows = self.interval_depths();
+        let job_name = |job: Option<usize>| match job {
+            Some(i) => self.jobs[i].as_str(),
+            None => "all",
+        };
+        rows.sort_by(|a, b| (a.0, job_name(a.1)).cmp(&(b.0, job_name(b.1))));
+        let (peak_running, periods) = self.saturated_periods();
+
+        match format {
+            ReportFormat::Text => {
+                writeln!(
+                    out_file,
+                    "{:<34} {:<24} {:>11} {:>11}",
+                    "Start", "Job", "Max Waiting", "Max Running"
+                )
+                .unwrap();
+                for (start, job, d) in &rows {
+                    writeln!(
+                        out_file,
+                        "{:<34} {:<24} {:>11} {:>11}",
+                        format_ms(*start),
+                        job_name(*job),
+                        d.waiting,
+                        d.running
+                    )
+                    .unwrap();
+                }
+                writeln!(out_file, "\nMost jobs running at once: {}", peak_running).unwrap();
+                writeln!(out_file, "Saturated (all running and jobs waiting):").unwrap();
+                if periods.is_empty() {
+                    writeln!(out_file, "    never").unwrap();
+                }
+                for p in &periods {
+                    writeln!(
+                        out_file,
+                        "    {} to {} ({}ms), max waiting: {}",
+                        format_ms(p.start),
+                        format_ms(p.end),
+                        p.end - p.start,
+                        p.max_waiting
+                    )
+                    .unwrap();
+                }
+            }
+            ReportFormat::Json => {
+                let intervals: Vec<serde_json::Value> = rows
+                    .iter()
+                    .map(|(start, job, d)| {
+                        serde_json::json!({
+                            "start": format_ms(*start),
+                            "job": job_name(*job),
+                            "max_waiting": d.waiting,
+                            "max_running": d.running,
+                        })
+                    })
+                    .collect();
+                let saturated: Vec<serde_json::Value> = periods
+                    .iter()
+                    .map(|p| {
+                        serde_json::json!({
+                            "start": format_ms(p.start),
+                            "end": format_ms(p.end),
+                            "duration_ms": p.end - p.start,
+                            "max_waiting": p.max_waiting,
+                        })
+                    })
+                    .collect();
+                let v = serde_json::json!({
+                    "interval_ms": self.interval_ms,
+                    "intervals": intervals,
+                    "peak_running": peak_running,
+                    "saturated": saturated,
+                });
+                writeln!(out_file, "{}", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+            }
+            ReportFormat::Csv => {
+                writeln!(out_file, "start,job,max_waiting,max_running").unwrap();
+                for (start, job, d) in &rows {
+                    writeln!(
+                        out_file,
+                        "{},{},{},{}",
+                        format_ms(*start),
+                        csv_quote(job_name(*job)),
+                        d.waiting,
+                        d.running
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latency_line(time: &str, job: &str, run: u64, wait: u64) -> String {
+        format!(
+            "2021-Feb-12 17:00:{} UTC LoadMonitor:WRN Job latency {{\"job\": \"{}\", \"run(ms)\": {}, \"wait(ms)\": {}, \"jlogId\": 1}}",
+            time, job, run, wait
+        )
+    }
+
+    fn queue(lines: &[String]) -> JobQueue {
+        let mut q = JobQueue::new(chrono::Duration::seconds(1));
+        for l in lines {
+            q.add(&LogLine::new(l).unwrap());
+        }
+        q.events.sort_by_key(|e| (e.time, e.kind));
+        q
+    }
+
+    #[test]
+    fn zero_run_job_does_not_split_saturated_period() {
+        let q = queue(&[
+            latency_line("05.000000000", "D", 0, 0),
+            latency_line("10.000000000", "A", 10000, 0),
+            latency_line("10.000000000", "B", 10000, 0),
+            latency_line("11.000000000", "C", 1000, 9000),
+        ]);
+        let (peak_running, periods) = q.saturated_periods();
+        assert_eq!(peak_running, 2);
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].end - periods[0].start, 9000);
+        assert_eq!(periods[0].max_waiting, 1);
+    }
+
+    #[test]
+    fn job_starting_as_another_ends_is_not_counted_twice() {
+        let q = queue(&[
+            latency_line("02.000000000", "A", 2000, 0),
+            latency_line("02.500000000", "B", 500, 0),
+        ]);
+        let rows = q.interval_depths();
+        assert!(rows.iter().all(|(_, _, d)| d.waiting == 0));
+        let all_running: Vec<i64> = rows
+            .iter()
+            .filter(|(_, job, _)| job.is_none())
+            .map(|(_, _, d)| d.running)
+            .collect();
+        assert_eq!(all_running, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn interval_depths_carry_over_intervals_without_events() {
+        let q = queue(&[
+            latency_line("01.200000000", "B", 200, 0),
+            latency_line("04.500000000", "A", 3000, 1500),
+        ]);
+        let rows = q.interval_depths();
+        let start = rows[0].0;
+        let depths = |job: Option<usize>| -> Vec<(i64, i64, i64)> {
+            rows.iter()
+                .filter(|(_, j, _)| *j == job)
+                .map(|(t, _, d)| (t - start, d.waiting, d.running))
+                .collect()
+        };
+        let a = q.job_index["A"];
+        let b = q.job_index["B"];
+        assert_eq!(
+            depths(Option::None),
+            vec![
+                (0, 1, 0),
+                (1000, 1, 1),
+                (2000, 0, 1),
+                (3000, 0, 1),
+                (4000, 0, 1)
+            ]
+        );
+        assert_eq!(
+            depths(Some(a)),
+            vec![
+                (0, 1, 0),
+                (1000, 1, 1),
+                (2000, 0, 1),
+                (3000, 0, 1),
+                (4000, 0, 1)
+            ]
+        );
+        assert_eq!(depths(Some(b)), vec![(1000, 0, 1)]);
+    }
+
+    #[test]
+    fn saturated_period_open_at_last_event_is_reported() {
+        let mut q = JobQueue::new(chrono::Duration::seconds(1));
+        for (time, kind, job) in [
+            (0, EventKind::Enqueue, 0),
+            (0, EventKind::Start, 0),
+            (2000, EventKind::Enqueue, 1),
+            (7000, EventKind::Enqueue, 2),
+        ] {
+            q.events.push(Event { time, kind, job });
+        }
+        let (peak_running, periods) = q.saturated_periods();
+        assert_eq!(peak_running, 1);
+        assert_eq!(periods.len(), 1);
+        assert_eq!((periods[0].start, periods[0].end), (2000, 7000));
+        assert_eq!(periods[0].max_waiting, 2);
+    }
+}
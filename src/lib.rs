@@ -0,0 +1,24 @@
+pub mod correlate;
+pub mod field_stats;
+pub mod filter;
+#[cfg(unix)]
+pub mod follow;
+pub mod group_rates;
+pub mod histogram_diff;
+pub mod jlog_id_report;
+pub mod job_latency;
+pub mod job_latency_diff;
+pub mod job_latency_slo;
+pub mod job_queue;
+pub mod log_input;
+pub mod log_line;
+pub mod log_line_histogram;
+pub mod log_linear_histogram;
+pub mod log_record;
+pub mod memmap_log;
+pub mod parse;
+pub mod rejects;
+pub mod report_format;
+pub mod template_miner;
+pub mod time_range;
+pub mod to_json;
@@ -0,0 +1,231 @@
+// Log input files
+
+// Plain log files are memory mapped (see `MemmapLog`). Rotated logs are often
+// compressed (debug.log.1.gz, debug.log.2.zst, ...), and those are decompressed
+// into memory instead. Either way, the contents of the log are available as a
+// single `str`.
+//
+// An input on the command line may be a glob pattern. All the files that match
+// are sorted by the time of their first line so rotated segments are read in
+// chronological order, as one logical log.
+//
+// Logs from several nodes may be analyzed together. Each node's inputs are one
+// logical log, and are labeled with the name of the node.
+
+use chrono::NaiveDateTime;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::memmap_log::MemmapLog;
+use crate::time_range;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+enum InputData {
+    Mapped(MemmapLog),
+    Decompressed(String),
+    Empty, // an empty file can't be memory mapped
+}
+
+pub struct LogInput {
+    pub path: PathBuf,
+    data: InputData,
+}
+
+fn decompress<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    })
+}
+
+impl LogInput {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        // Look at the first bytes of the file rather than the extension to
+        // decide if it is compressed
+        let mut magic = [0u8; 4];
+        let n = File::open(path)?.read(&mut magic)?;
+        let data = if n == 0 {
+            InputData::Empty
+        } else if n >= 2 && magic[..2] == GZIP_MAGIC {
+            let decoder = flate2::read::MultiGzDecoder::new(File::open(path)?);
+            InputData::Decompressed(decompress(decoder)?)
+        } else if n >= 4 && magic == ZSTD_MAGIC {
+            let decoder = zstd::stream::read::Decoder::new(File::open(path)?)?;
+            InputData::Decompressed(decompress(decoder)?)
+        } else {
+            InputData::Mapped(MemmapLog::new(&path.to_path_buf())?)
+        };
+        Ok(LogInput {
+            path: path.to_path_buf(),
+            data,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.data {
+            InputData::Mapped(m) => m.as_str(),
+            InputData::Decompressed(s) => s.as_str(),
+            InputData::Empty => "",
+        }
+    }
+
+    pub fn first_time(&self) -> Option<NaiveDateTime> {
+        time_range::first_time(self.as_str())
+    }
+
+    pub fn last_time(&self) -> Option<NaiveDateTime> {
+        time_range::last_time(self.as_str())
+    }
+}
+
+// Return true if the file is gzip or zstd compressed, by its extension or its
+// first bytes
+pub fn is_compressed(path: &Path) -> bool {
+    if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gz") | Some("zst")
+    ) {
+        return true;
+    }
+    let mut magic = [0u8; 4];
+    let n = match File::open(path).and_then(|mut f| f.read(&mut magic)) {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+    (n >= 2 && magic[..2] == GZIP_MAGIC) || (n >= 4 && magic == ZSTD_MAGIC)
+}
+
+// Return true if the input is a glob pattern rather than a file
+pub fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+// Expand glob patterns. A path that does not contain a pattern is used as is,
+// so a missing file is reported when it is opened.
+fn expand(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for p in paths {
+        let s = p.to_string_lossy();
+        if !is_pattern(p) {
+            result.push(p.clone());
+            continue;
+        }
+        let matches = match glob::glob(&s) {
+            Ok(matches) => matches,
+            Err(why) => {
+                eprintln!("Bad input pattern {}: {}", s, why);
+                std::process::exit(1);
+            }
+        };
+        let before = result.len();
+        result.extend(matches.filter_map(Result::ok).filter(|p| p.is_file()));
+        if result.len() == before {
+            eprintln!("No input files match {}", s);
+            std::process::exit(1);
+        }
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+// Open all the inputs and sort them in chronological order
+pub fn open_inputs(paths: &[PathBuf]) -> Vec<LogInput> {
+    let mut inputs: Vec<LogInput> = expand(paths)
+        .iter()
+        .map(|p| match LogInput::new(p) {
+            Err(why) => panic!("Couldn't open {}: {}", p.display(), why),
+            Ok(input) => input,
+        })
+        .collect();
+    // inputs without any timestamps sort first
+    inputs.sort_by_cached_key(|i| i.first_time());
+    inputs
+}
+
+// A node given on the command line as `<label>=<path or glob pattern>`
+pub struct NodeSpec {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for NodeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) if i > 0 && i + 1 < s.len() => Ok(NodeSpec {
+                label: s[..i].to_string(),
+                path: PathBuf::from(&s[i + 1..]),
+            }),
+            _ => Err(format!("Expected <label>=<path>, found {}", s)),
+        }
+    }
+}
+
+pub struct NodeInputs {
+    pub label: String, // empty for inputs that are not labeled
+    pub inputs: Vec<LogInput>,
+}
+
+impl NodeInputs {
+    pub fn new(label: &str, paths: &[PathBuf]) -> Self {
+        NodeInputs {
+            label: label.to_string(),
+            inputs: open_inputs(paths),
+        }
+    }
+
+    pub fn first_time(&self) -> Option<NaiveDateTime> {
+        self.inputs.iter().find_map(|i| i.first_time())
+    }
+
+    pub fn last_time(&self) -> Option<NaiveDateTime> {
+        self.inputs.iter().rev().find_map(|i| i.last_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn compressed_files_are_recognized() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let gz = dir.join(format!("compressed-{}", id));
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gz).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"log").unwrap();
+        encoder.finish().unwrap();
+        let plain = dir.join(format!("plain-{}.log", id));
+        std::fs::write(&plain, "log").unwrap();
+        assert!(is_compressed(&gz));
+        assert!(!is_compressed(&plain));
+        assert!(is_compressed(Path::new("debug.log.2.zst")));
+        std::fs::remove_file(&gz).unwrap();
+        std::fs::remove_file(&plain).unwrap();
+    }
+
+    #[test]
+    fn empty_file_is_an_empty_input() {
+        let path = std::env::temp_dir().join(format!("empty-{}.log", std::process::id()));
+        File::create(&path).unwrap();
+        let input = LogInput::new(&path);
+        std::fs::remove_file(&path).unwrap();
+        let input = input.unwrap();
+        assert_eq!(input.as_str(), "");
+        assert!(input.first_time().is_none());
+        assert!(input.last_time().is_none());
+    }
+}
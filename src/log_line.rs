@@ -1,8 +1,14 @@
+use chrono::NaiveDateTime;
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use std::fmt;
 use std::io::Write;
 
+// Format of the timestamp at the start of every log line:
+// 2021-Feb-05 13:52:54.660065778 UTC
+pub const TIMESTAMP_FORMAT: &str = "%Y-%b-%d %H:%M:%S%.f UTC";
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum LogLevel {
     Trace,
@@ -14,19 +20,52 @@ pub enum LogLevel {
 }
 
 impl LogLevel {
-    fn new(field: &str) -> Self {
+    fn new(field: &str) -> Option<Self> {
         match field {
-            "TRC" => LogLevel::Trace,
-            "DBG" => LogLevel::Debug,
-            "NFO" => LogLevel::Info,
-            "WRN" => LogLevel::Warning,
-            "ERR" => LogLevel::Error,
-            "FTL" => LogLevel::Fatal,
-            _ => panic!("Bad log level: {}", field),
+            "TRC" => Some(LogLevel::Trace),
+            "DBG" => Some(LogLevel::Debug),
+            "NFO" => Some(LogLevel::Info),
+            "WRN" => Some(LogLevel::Warning),
+            "ERR" => Some(LogLevel::Error),
+            "FTL" => Some(LogLevel::Fatal),
+            _ => Option::None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    // The line does not look like a log line at all
+    Malformed,
+    BadTimestamp(String),
+    BadLevel(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Malformed => write!(f, "Malformed log line"),
+            ParseError::BadTimestamp(ts) => write!(f, "Bad timestamp: {}", ts),
+            ParseError::BadLevel(level) => write!(f, "Bad log level: {}", level),
         }
     }
 }
 
+pub fn parse_timestamp(timestamp: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()
+}
+
+// Only the json data that mentions a `jlogId` is parsed
+fn parse_jlog_id(json_data: &str) -> Option<u64> {
+    if !json_data.contains("\"jlogId\"") {
+        return Option::None;
+    }
+    serde_json::from_str::<serde_json::Value>(json_data)
+        .ok()?
+        .get("jlogId")?
+        .as_u64()
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct LogLine<'a> {
     // Declaration order is important for sorting.
@@ -34,8 +73,12 @@ pub struct LogLine<'a> {
     pub module: &'a str,
     pub msg: &'a str,
     pub json_data: &'a str,
+    jlog_id: Option<u64>, // `jlogId` of the json data, parsed once when the line is parsed
+    pub continuation: &'a str, // lines after the first line of a multi-line record
+    pub time: NaiveDateTime, // parsed `timestamp`
     pub timestamp: &'a str,
-    pub line: &'a str, // raw line from the log
+    pub node: &'a str, // label of the node that wrote the line, empty if there is only one log
+    pub line: &'a str, // raw record from the log (may span several lines)
 }
 
 impl<'a> LogLine<'a> {
@@ -43,8 +86,10 @@ impl<'a> LogLine<'a> {
     // 2021-Feb-05 13:52:54.660065778 UTC TaggedCache:DBG LedgerCache target age set to 180000000000
     // Sample log line for structured log lines:
     // 2021-Feb-12 03:00:04.020060136 UTC LoadMonitor:WRN Job latency {"job": "TransactionAcquire", "run(ms)": 0, "wait(ms)": 1366, "jlogId": 115}
-
-    pub fn new(line: &'a str) -> Option<Self> {
+    //
+    // `record` is a logical record from `LogRecords`. Only its first line is
+    // parsed; any following lines are kept as the `continuation`.
+    pub fn new(record: &'a str) -> Result<Self, ParseError> {
         lazy_static! {
             static ref UNSTRUCTURED_RE: Regex = Regex::new(
                 r"(?x)
@@ -75,16 +120,25 @@ impl<'a> LogLine<'a> {
             .unwrap();
         }
 
-        let is_structured = line.ends_with("}");
+        let (line, continuation) = match record.find('\n') {
+            Some(i) => (record[..i].trim_end(), &record[i + 1..]),
+            None => (record, ""),
+        };
+
+        let is_structured = line.ends_with('}');
 
         let caps = if is_structured {
-            STRUCTURED_RE.captures(line)?
+            STRUCTURED_RE.captures(line)
         } else {
-            UNSTRUCTURED_RE.captures(line)?
-        };
+            UNSTRUCTURED_RE.captures(line)
+        }
+        .ok_or(ParseError::Malformed)?;
 
         let timestamp = caps.name("timestamp").unwrap().as_str();
-        let level = LogLevel::new(caps.name("level").unwrap().as_str());
+        let time = parse_timestamp(timestamp)
+            .ok_or_else(|| ParseError::BadTimestamp(timestamp.to_string()))?;
+        let level = caps.name("level").unwrap().as_str();
+        let level = LogLevel::new(level).ok_or_else(|| ParseError::BadLevel(level.to_string()))?;
         let module = caps.name("module").unwrap().as_str();
         let msg = caps.name("msg").unwrap().as_str();
         let json_data = if is_structured {
@@ -93,13 +147,17 @@ impl<'a> LogLine<'a> {
             ""
         };
 
-        Some(LogLine {
+        Ok(LogLine {
+            time,
             timestamp,
+            node: "",
             level,
             module,
             msg,
             json_data,
-            line,
+            jlog_id: parse_jlog_id(json_data),
+            continuation,
+            line: record,
         })
     }
 
@@ -109,13 +167,21 @@ impl<'a> LogLine<'a> {
                 return Some(jv);
             }
         }
-        return Option::None;
+        Option::None
+    }
+
+    // The id of the logging statement in rippled that wrote a structured line
+    pub fn jlog_id(&self) -> Option<u64> {
+        self.jlog_id
     }
 
     pub fn to_json_value(&self) -> Option<serde_json::Value> {
         let to_jval = |s: &str| -> serde_json::Value { serde_json::Value::String(s.to_string()) };
         let mut v = serde_json::json!({});
         v["timestamp"] = to_jval(self.timestamp);
+        if !self.node.is_empty() {
+            v["node"] = to_jval(self.node);
+        }
         v["module"] = to_jval(self.module);
         v["level"] = serde_json::Value::String(format!("{:?}", self.level));
         v["msg"] = to_jval(self.msg);
@@ -126,12 +192,18 @@ impl<'a> LogLine<'a> {
                 return Option::None;
             }
         }
+        if !self.continuation.is_empty() {
+            v["continuation"] = to_jval(self.continuation);
+        }
 
         Some(v)
     }
 
     // Return true if all data was written
     pub fn write_mixed_json(&self, out_file: &mut std::fs::File) -> bool {
+        if !self.node.is_empty() {
+            write!(out_file, "[{}] ", self.node).unwrap();
+        }
         write!(
             out_file,
             "{} {}:{:?} {}",
@@ -143,6 +215,9 @@ impl<'a> LogLine<'a> {
         } else if !self.json_data.is_empty() {
             return false;
         }
-        return true;
+        if !self.continuation.is_empty() {
+            write!(out_file, "\n{}", self.continuation).unwrap();
+        }
+        true
     }
 }
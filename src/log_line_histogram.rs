@@ -1,38 +1,162 @@
+use chrono::NaiveDateTime;
 use itertools::Itertools;
 
-use std::collections::BTreeSet;
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
 
 use crate::log_line::{LogLevel, LogLine};
+use crate::report_format::HistogramFormat;
+use crate::template_miner::TemplateMiner;
 
 #[derive(Debug, Eq, PartialEq)]
 struct HistogramElement<'a> {
     line: LogLine<'a>,
     count: u32,
+    node_counts: BTreeMap<&'a str, u32>, // count per node when logs from several nodes are merged
+    template: Option<usize>, // index of the template in the `TemplateMiner`, if templates are used
+    time_counts: BTreeMap<i64, u32>, // count per interval, by start in ms, if rates are recorded
+    first: (NaiveDateTime, &'a str), // time and timestamp of the first line
+    last: (NaiveDateTime, &'a str), // time and timestamp of the last line
 }
 
-impl<'a> PartialOrd for HistogramElement<'a> {
-    // Sort my level first, then by count, then by line
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match self.line.level.cmp(&other.line.level) {
-            Ordering::Less => return Some(Ordering::Greater),
-            Ordering::Greater => return Some(Ordering::Less),
-            Ordering::Equal => (),
+// Start of the interval of `interval_ms` that contains the line
+fn interval_start(l: &LogLine, interval_ms: i64) -> i64 {
+    let ms = l.time.timestamp_millis();
+    ms - ms.rem_euclid(interval_ms)
+}
+
+impl<'a> HistogramElement<'a> {
+    fn new(group: &[LogLine<'a>], template: Option<usize>) -> Self {
+        let mut node_counts = BTreeMap::new();
+        for l in group {
+            if !l.node.is_empty() {
+                *node_counts.entry(l.node).or_insert(0) += 1;
+            }
         }
-        match self.count.cmp(&other.count) {
-            Ordering::Less => return Some(Ordering::Greater),
-            Ordering::Greater => return Some(Ordering::Less),
-            Ordering::Equal => (),
+        let (first, last) = match group.iter().minmax_by_key(|l| l.time).into_option() {
+            Some((first, last)) => (first, last),
+            None => (&group[0], &group[0]),
+        };
+        HistogramElement {
+            line: group[0].clone(),
+            count: group.len() as u32,
+            node_counts,
+            template,
+            time_counts: BTreeMap::new(),
+            first: (first.time, first.timestamp),
+            last: (last.time, last.timestamp),
+        }
+    }
+
+    // The summary of the group, with a key that matches the same group in the
+    // histogram of another log. The key is the `GroupKey` of the lines, so it
+    // only works for the groups of a `StreamingHistogram`, where all the lines
+    // of a group have the same `GroupKey`.
+    fn summary(&self, templates: Option<&TemplateMiner>) -> GroupSummary {
+        let l = &self.line;
+        let (key, msg) = match GroupKey::new(l, self.template) {
+            GroupKey::JlogId { level, module, id } => (
+                format!("{:?} {} jlogId {}", level, module, id),
+                format!("{} (jlogId {})", l.msg, id),
+            ),
+            GroupKey::Message {
+                template: Some(i), ..
+            } => {
+                let t = templates
+                    .expect("template indexes come from the miner")
+                    .template(i);
+                (
+                    format!("template {}", t.id),
+                    format!("[{}] {}", t.id, t.text),
+                )
+            }
+            GroupKey::Message {
+                level,
+                module,
+                structured,
+                msg,
+                ..
+            } => (
+                format!("{:?} {} {} {}", level, module, structured, msg),
+                msg,
+            ),
+        };
+        GroupSummary {
+            key,
+            level: l.level,
+            count: self.count,
+            description: format!("{}:{:?} {}", l.module, l.level, msg),
+            time_counts: self.time_counts.clone(),
         }
-        Some(self.line.cmp(&other.line))
+    }
+
+    // For example:
+    // {
+    //   "count": 71,
+    //   "level": "Warning",
+    //   "module": "LoadMonitor",
+    //   "msg": "Job latency",
+    //   "first": "2021-Feb-13 22:00:00.486587000 UTC",
+    //   "last": "2021-Feb-13 22:32:27.469826000 UTC",
+    //   "example": {...}
+    // }
+    // with `template_id` and `template` if templates are used, `jlog_id` for
+    // structured lines, and `node_counts` when logs from several nodes are merged
+    fn to_json_value(&self, templates: Option<&TemplateMiner>) -> serde_json::Value {
+        let l = &self.line;
+        let mut v = serde_json::json!({
+            "count": self.count,
+            "level": format!("{:?}", l.level),
+            "module": l.module,
+            "msg": l.msg,
+            "first": self.first.1,
+            "last": self.last.1,
+        });
+        if let (Some(templates), Some(i)) = (templates, self.template) {
+            let t = templates.template(i);
+            v["template_id"] = serde_json::Value::String(t.id);
+            v["template"] = serde_json::Value::String(t.text);
+        }
+        if let Some(id) = l.jlog_id() {
+            v["jlog_id"] = serde_json::json!(id);
+        }
+        if !self.node_counts.is_empty() {
+            v["node_counts"] = serde_json::json!(self.node_counts);
+        }
+        v["example"] = l.to_json_value().unwrap_or(serde_json::Value::Null);
+        v
+    }
+
+    // For example: "node1: 40, node2: 31"
+    fn node_counts_string(&self) -> String {
+        self.node_counts
+            .iter()
+            .map(|(node, count)| format!("{}: {}", node, count))
+            .join(", ")
+    }
+}
+
+impl<'a> PartialOrd for HistogramElement<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl<'a> Ord for HistogramElement<'a> {
     // Sort my level first, then by count, then by line
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(&other).unwrap()
+        match self.line.level.cmp(&other.line.level) {
+            Ordering::Less => return Ordering::Greater,
+            Ordering::Greater => return Ordering::Less,
+            Ordering::Equal => (),
+        }
+        match self.count.cmp(&other.count) {
+            Ordering::Less => return Ordering::Greater,
+            Ordering::Greater => return Ordering::Less,
+            Ordering::Equal => (),
+        }
+        self.line.cmp(&other.line)
     }
 }
 
@@ -45,7 +169,7 @@ fn n_prefix(a: &str, b: &str) -> u32 {
                 return true;
             }
         }
-        return false;
+        false
     };
 
     let mut result: u32 = 0;
@@ -58,99 +182,180 @@ fn n_prefix(a: &str, b: &str) -> u32 {
         }
         result += 1;
     }
-    return result;
+    result
 }
 
-pub fn to_histogram(
-    log_lines: &BTreeSet<LogLine>,
-    histogram_out_file_name: &Option<std::path::PathBuf>,
-    grouped_out_file_name: &Option<std::path::PathBuf>,
-) {
-    let to_file = |fname: &Option<std::path::PathBuf>| -> Option<std::fs::File> {
-        if fname.is_none() {
-            return Option::None;
-        }
-        let fname = fname.as_ref().unwrap();
-        match std::fs::File::create(fname) {
-            Ok(file) => Some(file),
-            _ => {
-                eprintln!("Could not create file {} in to_histogram", fname.display());
-                std::process::exit(1);
+// Ignore lines whose first word contains non alphabetic characters
+// Allow ':' '[' ']' '<' '>'
+// Allow decimal numbers
+fn is_ignored(msg: &str) -> bool {
+    let mut has_alpha = false;
+    let mut has_num = false;
+    for c in msg.chars() {
+        if c.is_whitespace() {
+            return has_alpha == has_num;
+        }
+        if !c.is_alphabetic() {
+            if c == ':' || c == '[' || c == ']' || c == '<' || c == '>' {
+                continue;
+            }
+            if c.is_numeric() {
+                has_num = true;
             }
+        } else {
+            has_alpha = true;
         }
-    };
+    }
+    has_alpha == has_num
+}
 
-    let histogram_out_file: Option<std::fs::File> = to_file(histogram_out_file_name);
-    let mut grouped_out_file: Option<std::fs::File> = to_file(grouped_out_file_name);
+// The template of the element, as "[<id>] <template>"
+fn template_string(
+    element: &HistogramElement,
+    templates: Option<&TemplateMiner>,
+) -> Option<String> {
+    let t = templates?.template(element.template?);
+    Some(format!("[{}] {}", t.id, t.text))
+}
 
-    if histogram_out_file_name.is_none() && grouped_out_file.is_none() {
-        return;
+// Writes the groups as a json array, or as ndjson with one group per line, one
+// group at a time
+struct JsonGroupWriter<W: Write> {
+    out_file: W,
+    ndjson: bool,
+    n_groups: usize,
+}
+
+impl<W: Write> JsonGroupWriter<W> {
+    fn new(out_file: W, format: HistogramFormat) -> Self {
+        assert!(format != HistogramFormat::Text);
+        JsonGroupWriter {
+            out_file,
+            ndjson: format == HistogramFormat::Ndjson,
+            n_groups: 0,
+        }
     }
 
-    let mut write_group = |group: &Vec<LogLine>| {
-        if grouped_out_file.is_none() {
-            return;
+    fn write(&mut self, v: &serde_json::Value) {
+        if self.ndjson {
+            writeln!(self.out_file, "{}", v).unwrap();
+        } else {
+            let separator = if self.n_groups == 0 { "[\n" } else { ",\n" };
+            write!(
+                self.out_file,
+                "{}{}",
+                separator,
+                serde_json::to_string_pretty(v).unwrap()
+            )
+            .unwrap();
         }
-        let mut errors = Vec::with_capacity(1024);
+        self.n_groups += 1;
+    }
 
-        let mut out = grouped_out_file.as_mut().unwrap();
-        write!(out, ">>>> Group Size: {}\n", group.len()).unwrap();
-        for l in group {
-            if l.write_mixed_json(&mut out) {
-                write!(out, "\n").unwrap();
-            } else {
-                errors.push(l);
+    fn finish(mut self) {
+        if !self.ndjson {
+            if self.n_groups == 0 {
+                write!(self.out_file, "[").unwrap();
             }
+            writeln!(self.out_file, "\n]").unwrap();
         }
-        write!(out, "<<<<\n").unwrap();
+    }
+}
 
-        if !errors.is_empty() {
-            eprintln!("Error: Invalid json data >>>> ");
-            for e in &errors {
-                eprintln!("{:?}", e);
-            }
-            eprintln!("End Invalid json data <<<< ");
+fn write_histogram(
+    out_file: &mut std::fs::File,
+    histogram: &BTreeSet<HistogramElement>,
+    templates: Option<&TemplateMiner>,
+    format: HistogramFormat,
+) {
+    if format != HistogramFormat::Text {
+        let mut writer = JsonGroupWriter::new(out_file, format);
+        for element in histogram {
+            writer.write(&element.to_json_value(templates));
         }
-    };
+        writer.finish();
+        return;
+    }
 
-    // Ignore lines whose first word contains non alphabetic characters
-    // Allow ':' '[' ']' '<' '>'
-    // Allow decimal numbers
-    let ignore = |msg: &str| -> bool {
-        let mut has_alpha = false;
-        let mut has_num = false;
-        for c in msg.chars() {
-            if c.is_whitespace() {
-                return has_alpha == has_num;
-            }
-            if !c.is_alphabetic() {
-                if c == ':' || c == '[' || c == ']' || c == '<' || c == '>' {
-                    continue;
-                }
-                if c.is_numeric() {
-                    has_num = true;
-                }
-            } else {
-                has_alpha = true;
-            }
+    let mut prev_level = LogLevel::Trace;
+    for element in histogram {
+        let l = &element.line;
+        if l.level != prev_level {
+            write!(out_file, "\n{:?}\n", l.level).unwrap();
+            prev_level = l.level;
         }
-        return has_alpha == has_num;
-    };
+        write!(out_file, "{} : ", element.count).unwrap();
+        if let Some(template) = template_string(element, templates) {
+            writeln!(out_file, "{}", template).unwrap();
+        }
+        l.write_mixed_json(out_file);
+        if !element.node_counts.is_empty() {
+            write!(out_file, "\nBy node: {}", element.node_counts_string()).unwrap();
+        }
+        write!(out_file, "\n\n").unwrap();
+    }
+}
+
+fn create_file(fname: &std::path::PathBuf) -> std::fs::File {
+    match std::fs::File::create(fname) {
+        Ok(file) => file,
+        _ => {
+            eprintln!("Could not create file {} in to_histogram", fname.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+// Structured lines with a `jlogId` were written by the same logging statement
+// in rippled, so they are grouped by the id even when their message differs.
+// Like the other lines, they are only grouped with lines of the same level and
+// module. Returns the groups by id, and the other lines.
+fn split_by_jlog_id<'a, 'b>(
+    log_lines: &'b BTreeSet<LogLine<'a>>,
+) -> (Vec<Vec<LogLine<'a>>>, Vec<&'b LogLine<'a>>) {
+    let mut by_id = BTreeMap::<(LogLevel, &str, u64), Vec<LogLine>>::new();
+    let mut rest = Vec::new();
+    for l in log_lines {
+        match l.jlog_id() {
+            Some(id) => by_id
+                .entry((l.level, l.module, id))
+                .or_default()
+                .push(l.clone()),
+            None => rest.push(l),
+        }
+    }
+    (by_id.into_values().collect(), rest)
+}
 
-    let mut histogram = BTreeSet::<HistogramElement>::new();
+// Call `f` with every group, in the order of the first line of the groups
+fn call_sorted<'a, F: FnMut(&[LogLine<'a>])>(mut groups: Vec<Vec<LogLine<'a>>>, mut f: F) {
+    groups.sort_by(|a, b| a[0].cmp(&b[0]));
+    for group in &groups {
+        f(group);
+    }
+}
+
+// Group similar lines. Structured lines with a `jlogId` are grouped by the id
+// (see `split_by_jlog_id`). `log_lines` is sorted, so other similar lines are
+// next to each other: lines with json data are grouped by message, and other
+// lines by the number of starting words they have in common (see `n_prefix`).
+// `f` is called with every group. Returns the lines that are ignored (see
+// `is_ignored`).
+pub fn group_lines<'a, F: FnMut(&[LogLine<'a>])>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    f: F,
+) -> BTreeSet<LogLine<'a>> {
     let mut ignored = BTreeSet::<LogLine>::new();
+    let (mut groups, rest) = split_by_jlog_id(log_lines);
 
-    if log_lines.is_empty() {
-        return;
+    if rest.is_empty() {
+        call_sorted(groups, f);
+        return ignored;
     }
 
     let mut cur_group = Vec::<LogLine>::with_capacity(512);
     // insert the first line into the group
-    for first in log_lines {
-        // TODO: Use log_lines.first() when it stablizes
-        cur_group.push(first.clone());
-        break;
-    }
+    cur_group.push(rest[0].clone());
 
     let is_new_group = |n: u32, prev_n_prefix: u32, prev: &LogLine, cur: &LogLine| -> bool {
         if prev.json_data.is_empty() != cur.json_data.is_empty() {
@@ -163,55 +368,266 @@ pub fn to_histogram(
 
         assert!(prev.json_data.is_empty() && cur.json_data.is_empty());
 
-        return (!(prev_n_prefix == 0 && n != 0) && n < prev_n_prefix)
+        n < prev_n_prefix
             || (prev_n_prefix == 0 && n == 0)
             || prev.level != cur.level
-            || prev.module != cur.module;
+            || prev.module != cur.module
     };
 
     let mut prev_n_prefix = 0;
-    for (prev, cur) in log_lines.iter().tuple_windows() {
+    for (prev, cur) in rest.iter().tuple_windows() {
         let n = n_prefix(prev.msg, cur.msg);
 
-        if cur.json_data.is_empty() && ignore(cur.msg) {
-            ignored.insert(cur.clone());
+        if cur.json_data.is_empty() && is_ignored(cur.msg) {
+            ignored.insert((*cur).clone());
             continue;
         }
 
         assert!(!cur_group.is_empty());
         if is_new_group(n, prev_n_prefix, prev, cur) {
-            histogram.insert(HistogramElement {
-                line: cur_group[0].clone(),
-                count: cur_group.len() as u32,
-            });
-            write_group(&cur_group);
-            cur_group.clear();
-            cur_group.push(cur.clone());
+            groups.push(std::mem::replace(&mut cur_group, Vec::with_capacity(512)));
         }
 
         prev_n_prefix = n;
-        cur_group.push(cur.clone());
+        cur_group.push((*cur).clone());
     }
 
     assert!(!cur_group.is_empty());
-    histogram.insert(HistogramElement {
-        line: cur_group[0].clone(),
-        count: cur_group.len() as u32,
-    });
-    write_group(&cur_group);
+    groups.push(cur_group);
+    call_sorted(groups, f);
+    ignored
+}
 
-    let mut prev_level = LogLevel::Trace;
-    if let Some(mut out_file) = histogram_out_file {
-        for HistogramElement { line: l, count: c } in &histogram {
-            if l.level != prev_level {
-                write!(out_file, "\n{:?}\n", l.level).unwrap();
-                prev_level = l.level;
+// Group the lines by their template, or by their `jlogId` (see
+// `split_by_jlog_id`). `f` is called with every group. Returns the lines that
+// are ignored (see `is_ignored`).
+fn group_by_template<'a, F: FnMut(&[LogLine<'a>])>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    templates: &TemplateMiner,
+    f: F,
+) -> BTreeSet<LogLine<'a>> {
+    let mut ignored = BTreeSet::<LogLine>::new();
+    let (mut groups, rest) = split_by_jlog_id(log_lines);
+    let mut by_template = BTreeMap::<usize, Vec<LogLine>>::new();
+    for l in rest {
+        if l.json_data.is_empty() && is_ignored(l.msg) {
+            ignored.insert(l.clone());
+            continue;
+        }
+        match templates.find(l) {
+            Some(i) => by_template.entry(i).or_default().push(l.clone()),
+            None => {
+                ignored.insert(l.clone());
+            }
+        }
+    }
+    groups.extend(by_template.into_values());
+    call_sorted(groups, f);
+    ignored
+}
+
+// Writes a group of the grouped output, with the lines that aren't valid json
+// added to `errors`
+fn write_text_group<'a, 'b>(
+    out: &mut std::fs::File,
+    group: &'b [LogLine<'a>],
+    element: &HistogramElement,
+    templates: Option<&TemplateMiner>,
+    errors: &mut Vec<&'b LogLine<'a>>,
+) {
+    if element.node_counts.is_empty() {
+        write!(out, ">>>> Group Size: {}", group.len()).unwrap();
+    } else {
+        write!(
+            out,
+            ">>>> Group Size: {} ({})",
+            group.len(),
+            element.node_counts_string()
+        )
+        .unwrap();
+    }
+    match template_string(element, templates) {
+        Some(template) => writeln!(out, " Template: {}", template).unwrap(),
+        None => writeln!(out).unwrap(),
+    }
+    for l in group {
+        if l.write_mixed_json(out) {
+            writeln!(out).unwrap();
+        } else {
+            errors.push(l);
+        }
+    }
+    writeln!(out, "<<<<").unwrap();
+}
+
+// Group the lines with `group_lines`, or with `group_by_template` if there are
+// `templates`: with templates, lines are grouped by the templates mined from
+// all the lines instead of by the words they have in common. `f` is called
+// with every group. Returns the lines that are ignored.
+pub fn group<'a, F: FnMut(&[LogLine<'a>])>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    templates: Option<&TemplateMiner>,
+    f: F,
+) -> BTreeSet<LogLine<'a>> {
+    match templates {
+        Some(t) => group_by_template(log_lines, t, f),
+        None => group_lines(log_lines, f),
+    }
+}
+
+// Group the lines like the histogram does (see `group`). `f` is called with
+// every group and its element. Returns the elements, and the lines that are
+// ignored.
+fn histogram_elements<'a, F: FnMut(&[LogLine<'a>], &HistogramElement<'a>)>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    templates: Option<&TemplateMiner>,
+    mut f: F,
+) -> (Vec<HistogramElement<'a>>, BTreeSet<LogLine<'a>>) {
+    let mut elements = Vec::new();
+    let add_group = |group: &[LogLine<'a>]| {
+        let template = templates.and_then(|t| t.find(&group[0]));
+        let element = HistogramElement::new(group, template);
+        f(group, &element);
+        elements.push(element);
+    };
+    let ignored = group(log_lines, templates, add_group);
+    (elements, ignored)
+}
+
+// The groups of a log, for comparing them with the groups of another log (see
+// `histogram_diff`) and for the rates of the groups (see `group_rates`). The
+// lines are grouped like the `StreamingHistogram` groups them, by their
+// `GroupKey`, whether or not the log is streamed: unlike the common first words
+// of `group_lines`, the key of a line doesn't depend on the other lines of the
+// log, so the same message has the same key in every log. Unlike the
+// histogram, it doesn't borrow the lines.
+pub struct HistogramSummary {
+    pub(crate) groups: Vec<GroupSummary>,
+    pub(crate) lines: usize, // number of lines, including the ignored lines
+    pub(crate) duration: chrono::Duration, // time from the first to the last line
+    pub(crate) interval_ms: Option<i64>, // see `StreamingHistogram::record_rates`
+}
+
+impl HistogramSummary {
+    // Groups of different lines of the histogram that have the same key are
+    // summed
+    fn new<'e, 'a: 'e, I: Iterator<Item = &'e HistogramElement<'a>>>(
+        elements: I,
+        templates: Option<&TemplateMiner>,
+        lines: usize,
+        duration: chrono::Duration,
+        interval_ms: Option<i64>,
+    ) -> Self {
+        let mut groups = BTreeMap::<String, GroupSummary>::new();
+        for element in elements {
+            let g = element.summary(templates);
+            match groups.get_mut(&g.key) {
+                Some(existing) => {
+                    existing.count += g.count;
+                    for (start, count) in g.time_counts {
+                        *existing.time_counts.entry(start).or_insert(0) += count;
+                    }
+                }
+                None => {
+                    groups.insert(g.key.clone(), g);
+                }
             }
-            write!(out_file, "{} : ", c).unwrap();
-            l.write_mixed_json(&mut out_file);
-            write!(out_file, "\n\n").unwrap();
+        }
+        HistogramSummary {
+            groups: groups.into_values().collect(),
+            lines,
+            duration,
+            interval_ms,
+        }
+    }
+}
+
+// The summary of the groups of the lines (see `HistogramSummary`). With
+// `templates`, the lines are grouped by their templates, which must have been
+// mined from the lines. With `rates_interval`, the lines of every group are
+// also counted in every interval of time.
+pub fn summarize<'a>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    templates: Option<&TemplateMiner>,
+    rates_interval: Option<chrono::Duration>,
+) -> HistogramSummary {
+    let mut histogram = StreamingHistogram::new();
+    if let Some(interval) = rates_interval {
+        histogram.record_rates(interval);
+    }
+    for l in log_lines {
+        if histogram.count(l) {
+            histogram.add_to_group(l, templates.and_then(|t| t.find(l)));
         }
     }
+    histogram.summary_with(templates)
+}
+
+// Write the histogram and grouped files, if they are given
+pub fn to_histogram<'a>(
+    log_lines: &BTreeSet<LogLine<'a>>,
+    histogram_out_file_name: &Option<std::path::PathBuf>,
+    grouped_out_file_name: &Option<std::path::PathBuf>,
+    templates: Option<&TemplateMiner>,
+    format: HistogramFormat,
+) {
+    let to_file = |fname: &Option<std::path::PathBuf>| -> Option<std::fs::File> {
+        fname.as_ref().map(create_file)
+    };
+
+    let histogram_out_file: Option<std::fs::File> = to_file(histogram_out_file_name);
+    let grouped_out_file: Option<std::fs::File> = to_file(grouped_out_file_name);
+
+    let (mut grouped_text, mut grouped_json) = match format {
+        HistogramFormat::Text => (grouped_out_file, Option::None),
+        _ => (
+            Option::None,
+            grouped_out_file.map(|out| JsonGroupWriter::new(out, format)),
+        ),
+    };
+
+    let write_group = |group: &[LogLine], element: &HistogramElement| {
+        let mut errors = Vec::with_capacity(1024);
+
+        // the group with all its lines
+        if let Some(writer) = grouped_json.as_mut() {
+            let mut v = element.to_json_value(templates);
+            let lines: Vec<serde_json::Value> = group
+                .iter()
+                .filter_map(|l| {
+                    let line = l.to_json_value();
+                    if line.is_none() {
+                        errors.push(l);
+                    }
+                    line
+                })
+                .collect();
+            v["lines"] = serde_json::Value::Array(lines);
+            writer.write(&v);
+        }
+
+        if let Some(out) = grouped_text.as_mut() {
+            write_text_group(out, group, element, templates, &mut errors);
+        }
+
+        if !errors.is_empty() {
+            eprintln!("Error: Invalid json data >>>> ");
+            for e in &errors {
+                eprintln!("{:?}", e);
+            }
+            eprintln!("End Invalid json data <<<< ");
+        }
+    };
+
+    let (elements, ignored) = histogram_elements(log_lines, templates, write_group);
+
+    if let Some(writer) = grouped_json {
+        writer.finish();
+    }
+    if let Some(mut out_file) = histogram_out_file {
+        let histogram: BTreeSet<HistogramElement> = elements.into_iter().collect();
+        write_histogram(&mut out_file, &histogram, templates, format);
+    }
 
     if !ignored.is_empty() {
         eprintln!("\n\nIgnored Line In Histogram:");
@@ -220,3 +636,463 @@ pub fn to_histogram(
         eprintln!("{}", i.line);
     }
 }
+
+// When streaming, the lines can't all be kept in a sorted set the way
+// `to_histogram` needs. Instead structured lines with a `jlogId` are grouped by
+// level, module and id, and other lines by level, module and message, where words that
+// contain numbers match any other word with numbers (like `n_prefix` does), or
+// by the template mined from the lines so far. Only the count and the first
+// line of every group are kept.
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord)]
+enum GroupKey<'a> {
+    JlogId {
+        level: LogLevel,
+        module: &'a str,
+        id: u64,
+    },
+    Message {
+        level: LogLevel,
+        module: &'a str,
+        structured: bool,
+        msg: String,
+        template: Option<usize>, // index of the template, instead of the message
+    },
+}
+
+impl<'a> GroupKey<'a> {
+    fn new(l: &LogLine<'a>, template: Option<usize>) -> Self {
+        if let Some(id) = l.jlog_id() {
+            return GroupKey::JlogId {
+                level: l.level,
+                module: l.module,
+                id,
+            };
+        }
+        let structured = !l.json_data.is_empty();
+        let msg = if template.is_some() {
+            String::new()
+        } else if structured {
+            l.msg.to_string()
+        } else {
+            l.msg
+                .split(' ')
+                .map(|w| {
+                    if w.chars().any(|c| c.is_numeric()) {
+                        "<*>"
+                    } else {
+                        w
+                    }
+                })
+                .join(" ")
+        };
+        GroupKey::Message {
+            level: l.level,
+            module: l.module,
+            structured,
+            msg,
+            template,
+        }
+    }
+}
+
+// A group of a `HistogramSummary`, with a key that matches the same group in
+// the histogram of another log
+pub(crate) struct GroupSummary {
+    pub(crate) key: String,
+    pub(crate) level: LogLevel,
+    pub(crate) count: u32,
+    pub(crate) description: String, // for example "Peer:Warning Peer <*> disconnected"
+    pub(crate) time_counts: BTreeMap<i64, u32>, // see `StreamingHistogram::record_rates`
+}
+
+#[derive(Default)]
+pub struct StreamingHistogram<'a> {
+    groups: BTreeMap<GroupKey<'a>, HistogramElement<'a>>,
+    ignored: usize,
+    templates: Option<TemplateMiner>,
+    lines: usize,
+    first_time: Option<NaiveDateTime>,
+    last_time: Option<NaiveDateTime>,
+    interval_ms: Option<i64>,
+}
+
+impl<'a> StreamingHistogram<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Group the lines by the templates mined from them
+    pub fn with_templates() -> Self {
+        StreamingHistogram {
+            templates: Some(TemplateMiner::new()),
+            ..Self::default()
+        }
+    }
+
+    // Also count the lines of every group in every `interval` of time
+    pub fn record_rates(&mut self, interval: chrono::Duration) {
+        self.interval_ms = Some(interval.num_milliseconds().max(1));
+    }
+
+    pub fn add(&mut self, l: &LogLine<'a>) {
+        if self.count(l) {
+            let template = self.templates.as_mut().map(|t| t.add(l));
+            self.add_to_group(l, template);
+        }
+    }
+
+    // Count the line, and return whether it belongs in a group: false if it is
+    // ignored (see `is_ignored`)
+    fn count(&mut self, l: &LogLine<'a>) -> bool {
+        self.lines += 1;
+        self.first_time = Some(self.first_time.map_or(l.time, |t| t.min(l.time)));
+        self.last_time = Some(self.last_time.map_or(l.time, |t| t.max(l.time)));
+        if l.json_data.is_empty() && is_ignored(l.msg) {
+            self.ignored += 1;
+            return false;
+        }
+        true
+    }
+
+    // Add the line to the group of its `GroupKey`, with the index of its
+    // template if templates are used
+    fn add_to_group(&mut self, l: &LogLine<'a>, template: Option<usize>) {
+        let element = self
+            .groups
+            .entry(GroupKey::new(l, template))
+            .or_insert_with(|| HistogramElement {
+                line: l.clone(),
+                count: 0,
+                node_counts: BTreeMap::new(),
+                template,
+                time_counts: BTreeMap::new(),
+                first: (l.time, l.timestamp),
+                last: (l.time, l.timestamp),
+            });
+        element.count += 1;
+        if l.time < element.first.0 {
+            element.first = (l.time, l.timestamp);
+        }
+        if l.time > element.last.0 {
+            element.last = (l.time, l.timestamp);
+        }
+        if let Some(interval_ms) = self.interval_ms {
+            *element
+                .time_counts
+                .entry(interval_start(l, interval_ms))
+                .or_insert(0) += 1;
+        }
+        if !l.node.is_empty() {
+            *element.node_counts.entry(l.node).or_insert(0) += 1;
+        }
+    }
+
+    pub fn summary(&self) -> HistogramSummary {
+        self.summary_with(self.templates.as_ref())
+    }
+
+    // The summaries of a baseline log and of this histogram, for the histogram
+    // diff. `add_baseline` adds the lines of the baseline to a histogram that is
+    // grouped like this one. With templates, the templates of the baseline are
+    // mined on top of a copy of the templates of this histogram, and both
+    // summaries use the resulting templates, so a message has the same template
+    // in both.
+    pub fn diff_summaries<'b, F: FnOnce(&mut StreamingHistogram<'b>)>(
+        &self,
+        add_baseline: F,
+    ) -> (HistogramSummary, HistogramSummary) {
+        let mut baseline = StreamingHistogram::<'b> {
+            templates: self.templates.clone(),
+            ..Default::default()
+        };
+        add_baseline(&mut baseline);
+        (
+            baseline.summary(),
+            self.summary_with(baseline.templates.as_ref()),
+        )
+    }
+
+    // The summary with the templates of `templates`, which must have all the
+    // templates of this histogram at the same indexes
+    fn summary_with(&self, templates: Option<&TemplateMiner>) -> HistogramSummary {
+        let duration = match (self.first_time, self.last_time) {
+            (Some(first), Some(last)) => last - first,
+            _ => chrono::Duration::zero(),
+        };
+        HistogramSummary::new(
+            self.groups.values(),
+            templates,
+            self.lines,
+            duration,
+            self.interval_ms,
+        )
+    }
+
+    pub fn write(self, out_file_name: &std::path::PathBuf, format: HistogramFormat) {
+        let mut out_file = create_file(out_file_name);
+        let histogram: BTreeSet<HistogramElement> = self.groups.into_values().collect();
+        write_histogram(&mut out_file, &histogram, self.templates.as_ref(), format);
+        if self.ignored > 0 {
+            eprintln!("\n\nIgnored {} Lines In Histogram", self.ignored);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jlog_id_groups_are_split_by_level_and_module() {
+        let lines = [
+            "2021-Feb-13 22:15:20.113974252 UTC Ledger:NFO Built {\"seq\": 1, \"jlogId\": 7}",
+            "2021-Feb-13 22:15:21.113974252 UTC Ledger:NFO Built {\"seq\": 2, \"jlogId\": 7}",
+            "2021-Feb-13 22:15:22.113974252 UTC Ledger:ERR Built {\"seq\": 3, \"jlogId\": 7}",
+            "2021-Feb-13 22:15:23.113974252 UTC Shard:NFO Built {\"seq\": 4, \"jlogId\": 7}",
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let mut groups = Vec::new();
+        group_lines(&log_lines, |g| {
+            groups.push((g[0].level, g[0].module, g.len()))
+        });
+        groups.sort();
+        assert_eq!(
+            groups,
+            vec![
+                (LogLevel::Info, "Ledger", 2),
+                (LogLevel::Info, "Shard", 1),
+                (LogLevel::Error, "Ledger", 1),
+            ]
+        );
+
+        let mut streaming = StreamingHistogram::new();
+        for l in &log_lines {
+            streaming.add(l);
+        }
+        let mut counts: Vec<(LogLevel, u32)> = streaming
+            .summary()
+            .groups
+            .iter()
+            .map(|g| (g.level, g.count))
+            .collect();
+        counts.sort();
+        assert_eq!(
+            counts,
+            vec![
+                (LogLevel::Info, 1),
+                (LogLevel::Info, 2),
+                (LogLevel::Error, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn streaming_groups_differ_from_common_prefix_groups() {
+        let lines = [
+            "2021-Feb-13 22:15:20.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:15:21.000000000 UTC Peer:WRN Peer 2 connected",
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let mut sizes = Vec::new();
+        group_lines(&log_lines, |g| sizes.push(g.len()));
+        assert_eq!(sizes, vec![2]);
+
+        let mut streaming = StreamingHistogram::new();
+        for l in &log_lines {
+            streaming.add(l);
+        }
+        let mut keys: Vec<String> = streaming
+            .groups
+            .keys()
+            .map(|k| format!("{:?}", k))
+            .collect();
+        keys.sort();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].contains("Peer <*> connected"));
+        assert!(keys[1].contains("Peer <*> disconnected"));
+    }
+
+    #[test]
+    fn summary_groups_by_group_key() {
+        let lines = [
+            "2021-Feb-13 22:15:20.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:15:21.000000000 UTC Peer:WRN Peer 22 disconnected",
+            "2021-Feb-13 22:16:20.000000000 UTC Peer:WRN Peer 3 disconnected",
+            "2021-Feb-13 22:16:21.000000000 UTC Ledger:NFO Built {\"seq\": 1, \"jlogId\": 7}",
+            "2021-Feb-13 22:17:21.000000000 UTC Ledger:NFO Built {\"seq\": 2, \"jlogId\": 7}",
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        let summary = summarize(&log_lines, Option::None, Some(chrono::Duration::minutes(1)));
+        assert_eq!(summary.lines, 5);
+        assert_eq!(summary.duration, chrono::Duration::seconds(121));
+        let groups: Vec<(&str, u32, Vec<u32>)> = summary
+            .groups
+            .iter()
+            .map(|g| {
+                (
+                    g.description.as_str(),
+                    g.count,
+                    g.time_counts.values().copied().collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("Ledger:Info Built (jlogId 7)", 2, vec![1, 1]),
+                ("Peer:Warning Peer <*> disconnected", 3, vec![2, 1]),
+            ]
+        );
+
+        // streaming gives the same summary
+        let mut streaming = StreamingHistogram::new();
+        streaming.record_rates(chrono::Duration::minutes(1));
+        for l in &log_lines {
+            streaming.add(l);
+        }
+        let keys = |s: &HistogramSummary| -> Vec<(String, u32)> {
+            s.groups.iter().map(|g| (g.key.clone(), g.count)).collect()
+        };
+        assert_eq!(keys(&streaming.summary()), keys(&summary));
+    }
+
+    #[test]
+    fn diff_summaries_have_the_same_templates() {
+        let candidate_lines = [
+            "2021-Feb-13 22:15:20.000000000 UTC Peer:WRN Peer gone alpha",
+            "2021-Feb-13 22:15:21.000000000 UTC Peer:WRN Peer gone beta",
+        ];
+        let baseline_lines = ["2021-Feb-13 22:15:20.000000000 UTC Peer:WRN Peer gone alpha"];
+        let candidate_lines: BTreeSet<LogLine> = candidate_lines
+            .iter()
+            .map(|l| LogLine::new(l).unwrap())
+            .collect();
+        let baseline_lines: BTreeSet<LogLine> = baseline_lines
+            .iter()
+            .map(|l| LogLine::new(l).unwrap())
+            .collect();
+        let keys = |s: &HistogramSummary| -> Vec<(String, u32)> {
+            s.groups.iter().map(|g| (g.key.clone(), g.count)).collect()
+        };
+
+        // mined one log at a time, the message gets a template of its own in
+        // the baseline
+        let mined = |lines: &BTreeSet<LogLine>| {
+            let mut templates = TemplateMiner::new();
+            for l in lines {
+                templates.add(l);
+            }
+            templates
+        };
+        let (b, c) = (mined(&baseline_lines), mined(&candidate_lines));
+        assert_ne!(
+            keys(&summarize(&baseline_lines, Some(&b), Option::None))[0].0,
+            keys(&summarize(&candidate_lines, Some(&c), Option::None))[0].0
+        );
+
+        // mined together, both logs have the same template
+        let both = mined(&baseline_lines.union(&candidate_lines).cloned().collect());
+        let baseline = summarize(&baseline_lines, Some(&both), Option::None);
+        let candidate = summarize(&candidate_lines, Some(&both), Option::None);
+        assert_eq!(keys(&baseline)[0].0, keys(&candidate)[0].0);
+        assert_eq!(keys(&candidate)[0].1, 2);
+
+        // and the same when streaming
+        let mut streaming = StreamingHistogram::with_templates();
+        for l in &candidate_lines {
+            streaming.add(l);
+        }
+        let (streamed_baseline, streamed_candidate) = streaming.diff_summaries(|h| {
+            for l in &baseline_lines {
+                h.add(l);
+            }
+        });
+        assert_eq!(keys(&streamed_baseline), keys(&baseline));
+        assert_eq!(keys(&streamed_candidate), keys(&candidate));
+    }
+
+    fn json_groups(format: HistogramFormat, n: usize) -> String {
+        let mut out = Vec::new();
+        let mut writer = JsonGroupWriter::new(&mut out, format);
+        for i in 0..n {
+            writer.write(&serde_json::json!({"count": i, "msg": "a \"b\",\n c"}));
+        }
+        writer.finish();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_groups_are_an_array() {
+        let json = json_groups(HistogramFormat::Json, 3);
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let groups = v.as_array().unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[2]["count"], 2);
+        assert_eq!(groups[2]["msg"], "a \"b\",\n c");
+
+        let empty = json_groups(HistogramFormat::Json, 0);
+        assert_eq!(empty, "[\n]\n");
+        let v: serde_json::Value = serde_json::from_str(&empty).unwrap();
+        assert_eq!(v, serde_json::json!([]));
+    }
+
+    #[test]
+    fn ndjson_groups_are_one_object_per_line() {
+        let ndjson = json_groups(HistogramFormat::Ndjson, 3);
+        let groups: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(groups.len(), 3);
+        for (i, g) in groups.iter().enumerate() {
+            assert_eq!(g["count"], i);
+        }
+        assert_eq!(json_groups(HistogramFormat::Ndjson, 0), "");
+    }
+
+    #[test]
+    fn grouped_json_has_the_lines_of_every_group() {
+        let lines = [
+            "2021-Feb-13 22:15:20.000000000 UTC Peer:WRN Peer 1 disconnected",
+            "2021-Feb-13 22:15:21.000000000 UTC Peer:WRN Peer 2 disconnected",
+            "2021-Feb-13 22:16:21.000000000 UTC Ledger:NFO Built {\"seq\": 1, \"jlogId\": 7}",
+        ];
+        let log_lines: BTreeSet<LogLine> = lines.iter().map(|l| LogLine::new(l).unwrap()).collect();
+        for format in [HistogramFormat::Json, HistogramFormat::Ndjson] {
+            let path = std::env::temp_dir().join(format!("grouped-{}", std::process::id()));
+            to_histogram(
+                &log_lines,
+                &Option::None,
+                &Some(path.clone()),
+                Option::None,
+                format,
+            );
+            let out = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let mut groups: Vec<serde_json::Value> = match format {
+                HistogramFormat::Json => serde_json::from_str::<serde_json::Value>(&out)
+                    .unwrap()
+                    .as_array()
+                    .unwrap()
+                    .clone(),
+                _ => out
+                    .lines()
+                    .map(|l| serde_json::from_str(l).unwrap())
+                    .collect(),
+            };
+            groups.sort_by_key(|g| g["count"].as_u64());
+            assert_eq!(groups.len(), 2);
+            assert_eq!(groups[0]["jlog_id"], 7);
+            assert_eq!(groups[0]["lines"].as_array().unwrap().len(), 1);
+            assert_eq!(groups[0]["lines"][0]["data"]["seq"], 1);
+            assert_eq!(groups[1]["count"], 2);
+            let msgs: Vec<&serde_json::Value> = groups[1]["lines"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|l| &l["msg"])
+                .collect();
+            assert_eq!(msgs, ["Peer 1 disconnected", "Peer 2 disconnected"]);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+// Log-linear histogram of latencies, like HdrHistogram
+
+// Every power of two range of values is split into 2^sub_bucket_bits buckets
+// of the same width, so the relative resolution is the same for small and large
+// values. With 2 sub-bucket bits, the values 1024 to 2047 fall into the buckets
+// 1024-1279, 1280-1535, 1536-1791 and 1792-2047. Values below 2^sub_bucket_bits
+// each have their own bucket. With 0 sub-bucket bits the buckets are powers of
+// two.
+//
+// Histograms with the same number of sub-bucket bits can be merged, for example
+// to combine the histograms of several logs.
+//
+// The statistics of the job latencies and fields are recorded into histograms
+// with `RECORDING_SUB_BUCKET_BITS`, so memory doesn't grow with the number of
+// values. The min, max, mean and standard deviation are exact, percentiles are
+// within 1/128 of the values, and the buckets that are written are merged down
+// to the sub-bucket bits that were asked for.
+
+use std::io::Write;
+
+// number of stars printed at the bucket with the max count
+const HISTOGRAM_MAX_STARS: u64 = 32;
+
+// Sub-bucket bits of the histograms that the statistics are recorded into
+pub const RECORDING_SUB_BUCKET_BITS: u32 = 7;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogLinearHistogram {
+    sub_bucket_bits: u32,
+    counts: Vec<u64>,
+    min: u64,
+    max: u64,
+    sum: u128,
+    sum_squares: u128,
+}
+
+pub struct Bucket {
+    pub low: u64,  // smallest value in the bucket
+    pub high: u64, // largest value in the bucket
+    pub count: u64,
+}
+
+impl LogLinearHistogram {
+    pub fn new(sub_bucket_bits: u32) -> Self {
+        assert!(sub_bucket_bits < 16);
+        LogLinearHistogram {
+            sub_bucket_bits,
+            counts: Vec::new(),
+            min: u64::MAX,
+            max: 0,
+            sum: 0,
+            sum_squares: 0,
+        }
+    }
+
+    // A histogram to record statistics into, that can be written with
+    // `sub_bucket_bits` (see `with_sub_bucket_bits`)
+    pub fn for_recording(sub_bucket_bits: u32) -> Self {
+        LogLinearHistogram::new(sub_bucket_bits.max(RECORDING_SUB_BUCKET_BITS))
+    }
+
+    pub fn from_values(sub_bucket_bits: u32, values: &[u64]) -> Self {
+        let mut h = LogLinearHistogram::new(sub_bucket_bits);
+        for v in values {
+            h.record(*v);
+        }
+        h
+    }
+
+    fn index(&self, v: u64) -> usize {
+        let sub_buckets = 1u64 << self.sub_bucket_bits;
+        if v < sub_buckets {
+            return v as usize;
+        }
+        // number of low bits that are dropped
+        let shift = 63 - v.leading_zeros() - self.sub_bucket_bits;
+        ((shift as u64 + 1) * sub_buckets + ((v >> shift) & (sub_buckets - 1))) as usize
+    }
+
+    fn bucket_range(&self, index: usize) -> (u64, u64) {
+        let sub_buckets = 1u64 << self.sub_bucket_bits;
+        let index = index as u64;
+        if index < sub_buckets {
+            return (index, index);
+        }
+        let shift = index / sub_buckets - 1;
+        let low = (sub_buckets + index % sub_buckets) << shift;
+        (low, low + ((1u64 << shift) - 1))
+    }
+
+    pub fn record(&mut self, v: u64) {
+        self.record_n(v, 1);
+    }
+
+    fn record_n(&mut self, v: u64, n: u64) {
+        let i = self.index(v);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += n;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as u128 * n as u128;
+        self.sum_squares += (v as u128 * v as u128) * n as u128;
+    }
+
+    pub fn merge(&mut self, other: &LogLinearHistogram) -> Result<(), String> {
+        if self.sub_bucket_bits != other.sub_bucket_bits {
+            return Err(format!(
+                "Can't merge histograms with {} and {} sub-bucket bits",
+                self.sub_bucket_bits, other.sub_bucket_bits
+            ));
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (c, o) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *c += o;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        Ok(())
+    }
+
+    // The same values in the buckets of fewer sub-bucket bits. Every bucket
+    // is inside one of the larger buckets, so no value changes bucket.
+    pub fn with_sub_bucket_bits(&self, sub_bucket_bits: u32) -> Self {
+        assert!(sub_bucket_bits <= self.sub_bucket_bits);
+        let mut h = LogLinearHistogram::new(sub_bucket_bits);
+        for (i, count) in self.counts.iter().enumerate() {
+            if *count != 0 {
+                let i_low = h.index(self.bucket_range(i).0);
+                if i_low >= h.counts.len() {
+                    h.counts.resize(i_low + 1, 0);
+                }
+                h.counts[i_low] += count;
+            }
+        }
+        LogLinearHistogram {
+            sub_bucket_bits,
+            counts: h.counts,
+            min: self.min,
+            max: self.max,
+            sum: self.sum,
+            sum_squares: self.sum_squares,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // The counts of the buckets, by index. Histograms with the same sub-bucket
+    // bits have the same buckets at the same index.
+    pub(crate) fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    // The statistics below are only defined if the histogram is not empty
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count() as f64
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        let n = self.count() as f64;
+        let mean = self.sum as f64 / n;
+        (self.sum_squares as f64 / n - mean * mean).max(0.0).sqrt()
+    }
+
+    // Nearest rank percentile. The value is the highest value of the bucket of
+    // the rank, or the max if it is lower, so it is exact for the buckets of a
+    // single value.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let n = self.count();
+        assert!(n > 0);
+        let rank = ((p / 100.0 * n as f64).ceil() as u64).clamp(1, n);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self.bucket_range(i).1.clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    // The buckets from the first bucket that is not empty to the last one
+    pub fn buckets(&self) -> Vec<Bucket> {
+        let first = self.counts.iter().position(|c| *c != 0);
+        let last = self.counts.iter().rposition(|c| *c != 0);
+        match (first, last) {
+            (Some(first), Some(last)) => (first..=last)
+                .map(|i| {
+                    let (low, high) = self.bucket_range(i);
+                    Bucket {
+                        low,
+                        high,
+                        count: self.counts[i],
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // For example:
+    //   1024-1279  : 9      ***********
+    //   1280-1535  : 26     ********************************
+    pub fn write_ascii<W: Write>(&self, out: &mut W) {
+        let buckets = self.buckets();
+        let max_count = buckets.iter().map(|b| b.count).max().unwrap_or(1);
+        for b in buckets {
+            let range = if b.low == b.high {
+                b.low.to_string()
+            } else {
+                format!("{}-{}", b.low, b.high)
+            };
+            write!(out, "{:>13} : {:<6} ", range, b.count).unwrap();
+            let num_stars = b.count * HISTOGRAM_MAX_STARS / max_count;
+            for _ in 0..num_stars {
+                write!(out, "*").unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+    }
+
+    // For example: [{"low": 1024, "high": 1279, "count": 9}, ...]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::Value::Array(
+            self.buckets()
+                .iter()
+                .map(|b| serde_json::json!({"low": b.low, "high": b.high, "count": b.count}))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_and_bucket_range_round_trip() {
+        for bits in 0..16 {
+            let h = LogLinearHistogram::new(bits);
+            let mut values: Vec<u64> = (0..5000).collect();
+            for shift in 0..64 {
+                let p = 1u64 << shift;
+                values.extend([p - 1, p, p + 1, p | (p >> 1), u64::MAX - p]);
+            }
+            for v in values {
+                let (low, high) = h.bucket_range(h.index(v));
+                assert!(low <= v && v <= high, "{} not in {}-{}", v, low, high);
+                assert_eq!(h.index(low), h.index(v));
+                assert_eq!(h.index(high), h.index(v));
+            }
+        }
+    }
+
+    #[test]
+    fn buckets_are_contiguous() {
+        for bits in [0, 2, 5] {
+            let h = LogLinearHistogram::new(bits);
+            let mut next_low = 0;
+            for i in 0..h.index(1 << 20) {
+                let (low, high) = h.bucket_range(i);
+                assert_eq!(low, next_low);
+                assert!(low <= high);
+                next_low = high + 1;
+            }
+        }
+    }
+
+    #[test]
+    fn sub_buckets_split_powers_of_two() {
+        let h = LogLinearHistogram::from_values(2, &[1024, 1279, 1280, 2047, 3]);
+        let buckets: Vec<(u64, u64, u64)> = h
+            .buckets()
+            .iter()
+            .filter(|b| b.count > 0)
+            .map(|b| (b.low, b.high, b.count))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![(3, 3, 1), (1024, 1279, 2), (1280, 1535, 1), (1792, 2047, 1)]
+        );
+    }
+
+    #[test]
+    fn statistics_of_the_values() {
+        let values = [3, 1, 1000, 1100, 1900, 7];
+        let h = LogLinearHistogram::from_values(2, &values);
+        assert_eq!(h.count(), 6);
+        assert_eq!((h.min(), h.max()), (1, 1900));
+        assert_eq!(h.mean(), 4011.0 / 6.0);
+        let mean = 4011.0 / 6.0;
+        let variance = values
+            .iter()
+            .map(|v| (*v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / 6.0;
+        assert!((h.std_dev() - variance.sqrt()).abs() < 1e-9);
+        // the buckets of 2 sub-bucket bits are 1024-1279 and 1792-2047
+        assert_eq!(h.percentile(0.0), 1);
+        assert_eq!(h.percentile(50.0), 7);
+        assert_eq!(h.percentile(60.0), 1023);
+        assert_eq!(h.percentile(80.0), 1279);
+        assert_eq!(h.percentile(100.0), 1900);
+    }
+
+    #[test]
+    fn recorded_percentiles_are_close_to_the_values() {
+        let values: Vec<u64> = (0..20000).map(|i| i * 7 % 20011).collect();
+        let mut sorted = values.clone();
+        sorted.sort();
+        let h = LogLinearHistogram::from_values(RECORDING_SUB_BUCKET_BITS, &values);
+        for p in [1.0, 50.0, 90.0, 99.0, 99.9, 100.0] {
+            let exact = crate::job_latency::percentile(&sorted, p);
+            let v = h.percentile(p);
+            assert!(
+                v >= exact && v - exact <= exact / 128,
+                "p{}: {} {}",
+                p,
+                v,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn fewer_sub_bucket_bits_keep_the_values_in_their_buckets() {
+        let values: Vec<u64> = (0..5000).map(|i| i * i % 7919).collect();
+        let h = LogLinearHistogram::from_values(7, &values);
+        for bits in [0, 2, 7] {
+            let expected = LogLinearHistogram::from_values(bits, &values);
+            assert_eq!(h.with_sub_bucket_bits(bits), expected);
+        }
+    }
+
+    #[test]
+    fn merge_needs_the_same_sub_bucket_bits() {
+        let mut a = LogLinearHistogram::from_values(2, &[1, 100]);
+        let b = LogLinearHistogram::from_values(2, &[100, 5000]);
+        a.merge(&b).unwrap();
+        assert_eq!(a, LogLinearHistogram::from_values(2, &[1, 100, 100, 5000]));
+        assert!(a.merge(&LogLinearHistogram::new(3)).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+// Assemble physical log lines into logical log records
+
+// rippled sometimes writes a single log record across several physical lines.
+// Either the continuation lines have no timestamp at all (a message with
+// embedded newlines), or they repeat the timestamp (to the second) and module of
+// the previous line with a bare hash or a `{...}` body. For example:
+//
+// 2021-Feb-03 17:50:06.192191714 UTC LedgerConsensus:WRN View of consensus changed during open status=open,  mode=proposing
+// 2021-Feb-03 17:50:06.192217380 UTC LedgerConsensus:WRN E493A7F74B84F4B0F007451CCF2AFF367A9AD5173C4C21E8543E8F06CFA3AB40 to 9D7BF456677D3EAD508ACA936CACA1DB53B5782F84696A0B652017635FF2DC1E
+// 2021-Feb-03 17:50:06.192311774 UTC LedgerConsensus:WRN {"accepted":true, ... }
+//
+// `LogRecords` iterates through a buffer and returns each logical record as a
+// single `str` that may span several lines. Like `MemmapLog::as_str`, the
+// returned `str` points directly into the buffer.
+
+use itertools::Itertools;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LogRecord<'a> {
+    pub offset: usize,      // byte offset of the record in the buffer
+    pub line_number: usize, // line number (starting at 1) of the first line of the record
+    pub text: &'a str,
+}
+
+pub struct LogRecords<'a> {
+    buf: &'a str,
+    pos: usize,
+    end: usize,
+    line_number: usize,
+}
+
+impl<'a> LogRecords<'a> {
+    pub fn new(buf: &'a str) -> Self {
+        LogRecords {
+            buf,
+            pos: 0,
+            end: buf.len(),
+            line_number: 1,
+        }
+    }
+
+    // Only return the records in `buf[start..end]`. `start` and `end` should be
+    // record boundaries (see `record_start`). Offsets and line numbers are still
+    // relative to the start of `buf`.
+    pub fn range(buf: &'a str, start: usize, end: usize) -> Self {
+        let line_number = 1 + count_lines(&buf[..start]);
+        LogRecords::with_line_number(buf, start, end, line_number)
+    }
+
+    // Like `range`, when the line number at `start` is already known
+    pub fn with_line_number(buf: &'a str, start: usize, end: usize, line_number: usize) -> Self {
+        LogRecords {
+            buf,
+            pos: start,
+            end,
+            line_number,
+        }
+    }
+}
+
+pub fn count_lines(s: &str) -> usize {
+    s.bytes().filter(|c| *c == b'\n').count()
+}
+
+// Return the start of the first line at or after `pos`
+pub fn line_start(buf: &str, pos: usize) -> usize {
+    if pos == 0 || pos >= buf.len() {
+        return pos.min(buf.len());
+    }
+    if buf.as_bytes()[pos - 1] == b'\n' {
+        return pos;
+    }
+    match buf.as_bytes()[pos..].iter().position(|c| *c == b'\n') {
+        Some(i) => pos + i + 1,
+        None => buf.len(),
+    }
+}
+
+// Return true if the line starting at `pos` is the first line of a record
+fn starts_record(buf: &str, pos: usize) -> bool {
+    if pos == 0 || pos >= buf.len() {
+        return true;
+    }
+    let prev_start = match buf[..pos - 1].rfind('\n') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let line_end = line_start(buf, pos + 1);
+    let line = buf[pos..line_end].trim_end_matches('\n');
+    !is_continuation(&buf[prev_start..pos - 1], line)
+}
+
+// Return the start of the record that contains the line starting at `pos`
+pub fn record_start(buf: &str, pos: usize) -> usize {
+    let mut pos = pos;
+    while !starts_record(buf, pos) {
+        pos = match buf[..pos - 1].rfind('\n') {
+            Some(i) => i + 1,
+            None => 0,
+        };
+    }
+    pos
+}
+
+// Return the start of the first record that begins at or after the line
+// starting at `pos`
+pub fn next_record_start(buf: &str, pos: usize) -> usize {
+    let mut pos = pos;
+    while pos < buf.len() && !starts_record(buf, pos) {
+        pos = line_start(buf, pos + 1);
+    }
+    pos
+}
+
+// Split `buf[start..end]` into about `n` pieces that begin and end on record
+// boundaries, so each piece can be given to `LogRecords::range`
+pub fn split_records(buf: &str, start: usize, end: usize, n: usize) -> Vec<(usize, usize)> {
+    let chunk_size = ((end - start) / n.max(1)).max(1);
+    let mut bounds = vec![start];
+    let mut pos = start;
+    while pos + chunk_size < end {
+        // don't split a multi-line record
+        let split = next_record_start(buf, line_start(buf, pos + chunk_size));
+        if split >= end {
+            break;
+        }
+        bounds.push(split);
+        pos = split;
+    }
+    bounds.push(end);
+    bounds.iter().copied().tuple_windows().collect()
+}
+
+// Length of the "2021-Feb-03 17:50:06" prefix of a timestamp
+const TIMESTAMP_SECONDS_LEN: usize = 20;
+
+// Return true if the line starts with a timestamp like "2021-Feb-03 17:50:06"
+pub fn starts_with_timestamp(line: &str) -> bool {
+    let b = line.as_bytes();
+    if b.len() < TIMESTAMP_SECONDS_LEN {
+        return false;
+    }
+    let digits = |r: std::ops::Range<usize>| b[r].iter().all(|c| c.is_ascii_digit());
+    digits(0..4)
+        && b[4] == b'-'
+        && b[5..8].iter().all(|c| c.is_ascii_alphabetic())
+        && b[8] == b'-'
+        && digits(9..11)
+        && b[11] == b' '
+        && digits(12..14)
+        && b[14] == b':'
+        && digits(15..17)
+        && b[17] == b':'
+        && digits(18..20)
+}
+
+// Split a timestamped line into its "Module:LVL" field and the text that follows
+fn module_and_body(line: &str) -> Option<(&str, &str)> {
+    let rest = &line[line.find(" UTC ")? + 5..];
+    let split = rest.find(' ')?;
+    Some((&rest[..split], &rest[split + 1..]))
+}
+
+fn is_hash(word: &str) -> bool {
+    word.len() == 64 && word.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+// Return true if `line` belongs to the same record as the physical line `prev`
+// that precedes it
+pub fn is_continuation(prev: &str, line: &str) -> bool {
+    if !starts_with_timestamp(line) {
+        return true;
+    }
+    if !starts_with_timestamp(prev)
+        || prev[..TIMESTAMP_SECONDS_LEN] != line[..TIMESTAMP_SECONDS_LEN]
+    {
+        return false;
+    }
+    match (module_and_body(prev), module_and_body(line)) {
+        (Some((prev_module, _)), Some((module, body))) => {
+            if prev_module != module {
+                return false;
+            }
+            let body = body.trim_end();
+            (body.starts_with('{') && body.ends_with('}'))
+                || is_hash(body.split(' ').next().unwrap_or(""))
+        }
+        _ => false,
+    }
+}
+
+impl<'a> Iterator for LogRecords<'a> {
+    type Item = LogRecord<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip blank lines between records
+        let bytes = self.buf.as_bytes();
+        while self.pos < self.end && bytes[self.pos].is_ascii_whitespace() {
+            if bytes[self.pos] == b'\n' {
+                self.line_number += 1;
+            }
+            self.pos += 1;
+        }
+        if self.pos >= self.end {
+            return Option::None;
+        }
+
+        let rest = &self.buf[self.pos..self.end];
+        let line_end = |start: usize| -> usize {
+            match rest[start..].find('\n') {
+                Some(i) => start + i,
+                None => rest.len(),
+            }
+        };
+
+        // `end` is the end of the last line that is part of this record
+        let mut end = line_end(0);
+        let mut prev = &rest[..end];
+        while end < rest.len() {
+            let next_end = line_end(end + 1);
+            let line = &rest[end + 1..next_end];
+            if !is_continuation(prev, line) {
+                break;
+            }
+            end = next_end;
+            prev = line;
+        }
+
+        let record = LogRecord {
+            offset: self.pos,
+            line_number: self.line_number,
+            // blank lines at the end of a record are not part of the record
+            text: rest[..end].trim_end(),
+        };
+        let consumed = (end + 1).min(rest.len());
+        self.line_number += rest[..consumed].bytes().filter(|c| *c == b'\n').count();
+        self.pos += consumed;
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+2021-Feb-03 17:50:06.192191714 UTC LedgerConsensus:WRN View of consensus changed during open status=open,  mode=proposing
+2021-Feb-03 17:50:06.192217380 UTC LedgerConsensus:WRN E493A7F74B84F4B0F007451CCF2AFF367A9AD5173C4C21E8543E8F06CFA3AB40 to 9D7BF456677D3EAD508ACA936CACA1DB53B5782F84696A0B652017635FF2DC1E
+2021-Feb-03 17:50:06.192311774 UTC LedgerConsensus:WRN {\"accepted\":true}
+2021-Feb-03 17:50:06.200000000 UTC Peer:NFO Connected
+2021-Feb-03 17:50:06.300000000 UTC LedgerHistory:ERR valid
+first line without a timestamp
+second line without a timestamp
+
+2021-Feb-03 17:50:06.400000000 UTC Peer:NFO {\"not\": \"a continuation\"}
+";
+
+    fn texts<'a>(records: LogRecords<'a>) -> Vec<(usize, &'a str)> {
+        records.map(|r| (r.line_number, r.text)).collect()
+    }
+
+    #[test]
+    fn continuation_lines_are_joined() {
+        let records = texts(LogRecords::new(LOG));
+        let lines: Vec<&str> = LOG.lines().collect();
+        assert_eq!(
+            records,
+            vec![
+                (1, &LOG[..LOG.find("\n2021-Feb-03 17:50:06.2000").unwrap()]),
+                (4, lines[3]),
+                (5, &lines[4..7].join("\n")[..]),
+                (9, lines[8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn view_of_consensus_changed_is_one_record() {
+        let log: Vec<&str> = LOG.lines().take(3).collect();
+        assert!(is_continuation(log[0], log[1]));
+        assert!(is_continuation(log[1], log[2]));
+        let records = texts(LogRecords::new(LOG));
+        assert!(records[0].1.starts_with(log[0]));
+        assert!(records[0].1.ends_with(log[2]));
+    }
+
+    #[test]
+    fn same_module_at_another_time_is_a_new_record() {
+        let log = "\
+2021-Feb-03 17:50:06.192191714 UTC LedgerConsensus:WRN View of consensus changed during open status=open,  mode=proposing
+2021-Feb-03 17:50:07.192217380 UTC LedgerConsensus:WRN E493A7F74B84F4B0F007451CCF2AFF367A9AD5173C4C21E8543E8F06CFA3AB40 to 9D7BF456677D3EAD508ACA936CACA1DB53B5782F84696A0B652017635FF2DC1E
+2021-Feb-03 17:50:08.192311774 UTC LedgerConsensus:WRN {\"accepted\":true}
+";
+        let lines: Vec<&str> = log.lines().collect();
+        assert!(!is_continuation(lines[0], lines[1]));
+        assert!(!is_continuation(lines[1], lines[2]));
+        assert_eq!(
+            texts(LogRecords::new(log)),
+            vec![(1, lines[0]), (2, lines[1]), (3, lines[2])]
+        );
+    }
+
+    #[test]
+    fn split_records_does_not_split_multi_line_records() {
+        let all = texts(LogRecords::new(LOG));
+        for n in 1..=LOG.len() {
+            let chunks = split_records(LOG, 0, LOG.len(), n);
+            assert_eq!(chunks.first().unwrap().0, 0);
+            assert_eq!(chunks.last().unwrap().1, LOG.len());
+            for ((_, e), (s, _)) in chunks.iter().tuple_windows() {
+                assert_eq!(e, s);
+                assert!(starts_record(LOG, *s));
+            }
+            let joined: Vec<(usize, &str)> = chunks
+                .iter()
+                .flat_map(|(s, e)| texts(LogRecords::range(LOG, *s, *e)))
+                .collect();
+            assert_eq!(joined, all, "split into {} pieces", n);
+        }
+    }
+}
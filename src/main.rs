@@ -1,25 +1,66 @@
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use std::cell::RefCell;
 use std::collections::BTreeSet;
-use std::error::Error;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
 
 use structopt::StructOpt;
 
-mod job_latency;
-mod log_line;
-mod log_line_histogram;
-mod memmap_log;
-mod to_json;
+use rs_log_analyzer::correlate::Spikes;
+use rs_log_analyzer::field_stats::{FieldStats, StatsSpec};
+use rs_log_analyzer::filter::Filter;
+#[cfg(unix)]
+use rs_log_analyzer::follow::Follower;
+use rs_log_analyzer::jlog_id_report::JlogIdReport;
+use rs_log_analyzer::job_latency::{JobLatencySeries, JobLatencyStats};
+use rs_log_analyzer::job_latency_slo::JobLatencySlo;
+use rs_log_analyzer::job_queue::JobQueue;
+#[cfg(unix)]
+use rs_log_analyzer::log_input::{is_compressed, is_pattern};
+use rs_log_analyzer::log_input::{NodeInputs, NodeSpec};
+use rs_log_analyzer::log_line::LogLine;
+use rs_log_analyzer::log_line_histogram::StreamingHistogram;
+use rs_log_analyzer::rejects::Rejects;
+use rs_log_analyzer::report_format::{HistogramFormat, ReportFormat};
+use rs_log_analyzer::template_miner::TemplateMiner;
+use rs_log_analyzer::time_range::{duration_arg, TimeBound, TimeRange};
+use rs_log_analyzer::to_json::JsonWriter;
+use rs_log_analyzer::{
+    group_rates, histogram_diff, job_latency_diff, log_line_histogram, parse, to_json,
+};
+
+// How long to wait before looking for new data in follow mode
+#[cfg(unix)]
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-use log_line::LogLine;
+fn sub_bucket_bits_arg(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(n) if n < 16 => Ok(n),
+        _ => Err(format!(
+            "Expected a number of bits from 0 to 15, found {}",
+            s
+        )),
+    }
+}
 
 #[derive(StructOpt)]
 struct Cli {
     #[structopt(
         short = "i",
         long = "input",
-        help = "rippled generated input log file",
-        parse(from_os_str)
+        help = "rippled generated input log files or glob patterns; gzip and zstd files are decompressed",
+        parse(from_os_str),
+        required_unless = "nodes"
+    )]
+    input_log_files: Vec<std::path::PathBuf>,
+    #[structopt(
+        short = "n",
+        long = "node",
+        help = "log files from one node of a network, as <label>=<path or glob pattern>; logs from several nodes are merged by time"
     )]
-    input_log_file: std::path::PathBuf,
+    nodes: Vec<NodeSpec>,
     #[structopt(
         short = "h",
         long = "histogram",
@@ -41,6 +82,12 @@ struct Cli {
         parse(from_os_str)
     )]
     grouped_file: Option<std::path::PathBuf>,
+    #[structopt(
+        long = "histogram-format",
+        default_value = "text",
+        help = "format of the histogram and grouped files: text, json or ndjson"
+    )]
+    histogram_format: HistogramFormat,
 
     #[structopt(
         short = "l",
@@ -50,49 +97,858 @@ struct Cli {
     )]
     job_latency_file: Option<std::path::PathBuf>,
 
+    #[structopt(
+        long = "job-latency-series",
+        help = "job latency count, max and p99 per job for every --interval of time",
+        parse(from_os_str)
+    )]
+    job_latency_series_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "job-queue",
+        help = "number of jobs waiting and running per job type for every --interval of time, reconstructed from the job latency lines",
+        parse(from_os_str)
+    )]
+    job_queue_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "stats",
+        help = "stats of numeric fields of the lines with the --stats-msg message, or of a --stats-preset",
+        parse(from_os_str)
+    )]
+    stats_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "stats-msg",
+        help = "message of the structured lines the stats are computed for, for example \"cache target size is set\""
+    )]
+    stats_msg: Option<String>,
+
+    #[structopt(
+        long = "stats-group-by",
+        help = "json field the stats are grouped by, for example cacheName"
+    )]
+    stats_group_by: Option<String>,
+
+    #[structopt(
+        long = "stats-field",
+        help = "numeric json field the stats are computed for; may be given more than once"
+    )]
+    stats_fields: Vec<String>,
+
+    #[structopt(
+        long = "stats-preset",
+        help = "predefined message, group by and fields for the stats: job-latency",
+        conflicts_with_all = &["stats-msg", "stats-group-by", "stats-fields"]
+    )]
+    stats_preset: Option<StatsSpec>,
+
+    #[structopt(
+        long = "sub-bucket-bits",
+        default_value = "2",
+        parse(try_from_str = sub_bucket_bits_arg),
+        help = "split every power of two bucket of the job latency and stats histograms into 2^n buckets; 0 uses power of two buckets"
+    )]
+    sub_bucket_bits: u32,
+
+    #[structopt(
+        long = "interval",
+        default_value = "1m",
+        parse(try_from_str = duration_arg),
+        help = "length of the intervals of the job latency series, job queue and group rates, for example 10s or 1m"
+    )]
+    interval: chrono::Duration,
+
+    #[structopt(
+        long = "job-latency-slo",
+        help = "check job latencies against the thresholds in this file, for example: InboundLedger wait p99 < 2000ms; exits with 2 if any check fails",
+        parse(from_os_str)
+    )]
+    job_latency_slo_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "baseline",
+        help = "baseline log files or glob patterns to compare the job latencies and histogram of the input with",
+        parse(from_os_str)
+    )]
+    baseline_log_files: Vec<std::path::PathBuf>,
+
+    #[structopt(
+        long = "job-latency-diff",
+        help = "compare the job latencies of the input with the --baseline logs",
+        parse(from_os_str),
+        requires = "baseline-log-files"
+    )]
+    job_latency_diff_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "tolerance",
+        default_value = "10",
+        help = "percent a job's mean or p99 latency may grow over the baseline before it is marked as regressed"
+    )]
+    tolerance: f64,
+
+    #[structopt(
+        long = "histogram-diff",
+        help = "compare the histogram of the input with the histogram of the --baseline logs, as rates over the duration of each log",
+        parse(from_os_str),
+        requires = "baseline-log-files"
+    )]
+    histogram_diff_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "group-rates",
+        help = "count of every group of the histogram diff in every --interval of time, drawn as a sparkline in the text format",
+        parse(from_os_str)
+    )]
+    group_rates_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "correlate",
+        help = "messages that are logged more often around job latency spikes than elsewhere in the log",
+        parse(from_os_str)
+    )]
+    correlate_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "spike-threshold",
+        default_value = "1000",
+        help = "job latency lines with a wait time over this many ms are spikes"
+    )]
+    spike_threshold: u64,
+
+    #[structopt(
+        long = "spike-job",
+        help = "only job latency lines of this job are spikes"
+    )]
+    spike_job: Option<String>,
+
+    #[structopt(
+        long = "window",
+        default_value = "5s",
+        parse(try_from_str = duration_arg),
+        help = "lines logged this close to a spike are near the spike"
+    )]
+    window: chrono::Duration,
+
+    #[structopt(
+        long = "format",
+        default_value = "text",
+        help = "format of the job latency report, series, diff, job queue, stats, jlogId report, histogram diff and group rates: text, json or csv"
+    )]
+    format: ReportFormat,
+
+    #[structopt(
+        long = "jlog-report",
+        help = "jlogIds whose structured lines have different messages, modules or levels",
+        parse(from_os_str)
+    )]
+    jlog_report_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "templates",
+        help = "group the histogram and grouped output by templates mined from the messages, and add the template ids to the json output"
+    )]
+    templates: bool,
+
     #[structopt(
         short = "m",
         long = "mixed-json",
         help = "Use mixed json when writing the json file"
     )]
     mixed_json: bool,
+
+    #[structopt(
+        short = "r",
+        long = "rejects",
+        help = "write log lines that could not be parsed to this file",
+        parse(from_os_str)
+    )]
+    reject_file: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "since",
+        allow_hyphen_values = true,
+        help = "only analyze lines at or after this time: a timestamp, first+<duration>, or last-<duration>"
+    )]
+    since: Option<TimeBound>,
+
+    #[structopt(
+        long = "until",
+        allow_hyphen_values = true,
+        help = "only analyze lines at or before this time: a timestamp, first+<duration>, or last-<duration>"
+    )]
+    until: Option<TimeBound>,
+
+    #[structopt(
+        short = "f",
+        long = "filter",
+        help = "only analyze lines that match this filter expression, for example: level>=WRN && module=~\"Ledger.*\""
+    )]
+    filter: Option<Filter>,
+
+    #[structopt(
+        long = "threads",
+        default_value = "0",
+        help = "number of threads used to parse the log; 0 uses all the cores, 1 parses on a single thread"
+    )]
+    threads: usize,
+
+    #[structopt(
+        long = "stream",
+        help = "analyze the log one line at a time instead of keeping every line in memory; the histogram groups lines by message, with words that contain numbers as wildcards, rather than by their first words in common, so its groups may differ; the grouped output is not available"
+    )]
+    stream: bool,
+
+    #[cfg(unix)]
+    #[structopt(
+        long = "follow",
+        help = "keep reading the input log file as it grows, like tail -f; only the json, job latency and stats outputs are available"
+    )]
+    follow: bool,
+
+    #[cfg(unix)]
+    #[structopt(
+        long = "follow-interval",
+        default_value = "10s",
+        parse(try_from_str = duration_arg),
+        help = "how often the job latency stats and series are rewritten in follow mode"
+    )]
+    follow_interval: chrono::Duration,
+}
+
+// What the --stats output summarizes: a preset, or the message and fields given
+// on the command line
+fn stats_spec(args: &Cli) -> Option<StatsSpec> {
+    args.stats_file.as_ref()?;
+    if let Some(preset) = &args.stats_preset {
+        return Some(preset.clone());
+    }
+    match &args.stats_msg {
+        Some(msg) if !args.stats_fields.is_empty() => Some(StatsSpec {
+            msg: msg.clone(),
+            group_by: args.stats_group_by.clone(),
+            fields: args.stats_fields.clone(),
+            preset: Option::None,
+        }),
+        _ => {
+            eprintln!(
+                "--stats needs --stats-preset, or --stats-msg and at least one --stats-field"
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+// Job latency stats of the baseline logs, for the job latency diff
+fn baseline_latency(args: &Cli) -> JobLatencyStats {
+    let baseline = NodeInputs::new("", &args.baseline_log_files);
+    let mut stats = JobLatencyStats::new(args.sub_bucket_bits);
+    for_each_baseline_line(args, &baseline, |l| stats.add(l));
+    stats
+}
+
+// Call `f` with every line of the baseline logs that matches the filter
+fn for_each_baseline_line<'a, F: FnMut(&LogLine<'a>)>(
+    args: &Cli,
+    baseline: &'a NodeInputs,
+    mut f: F,
+) {
+    let rejects = RefCell::new(Rejects::new(&Option::None));
+    for input in &baseline.inputs {
+        for l in parse::stream_input(input, "", &Option::None, &rejects) {
+            if let Some(filter) = &args.filter {
+                if !filter.matches(&l) {
+                    continue;
+                }
+            }
+            f(&l);
+        }
+    }
+    let mut rejects = rejects.into_inner();
+    if rejects.count() > 0 {
+        eprint!("Baseline: ");
+        rejects.report();
+    }
+}
+
+fn streaming_histogram<'a>(args: &Cli) -> StreamingHistogram<'a> {
+    let mut histogram = if args.templates {
+        StreamingHistogram::with_templates()
+    } else {
+        StreamingHistogram::new()
+    };
+    if args.group_rates_file.is_some() {
+        histogram.record_rates(args.interval);
+    }
+    histogram
+}
+
+// Write the histogram diff of the baseline and the input lines. With
+// `--templates`, the templates are mined from the lines of both.
+fn write_histogram_diff(args: &Cli, lines: &BTreeSet<LogLine>) {
+    if let Some(out) = &args.histogram_diff_file {
+        let inputs = NodeInputs::new("", &args.baseline_log_files);
+        let mut baseline_lines = BTreeSet::new();
+        for_each_baseline_line(args, &inputs, |l| {
+            baseline_lines.insert(l.clone());
+        });
+        let templates = args.templates.then(|| {
+            let mut templates = TemplateMiner::new();
+            for l in baseline_lines.iter().chain(lines) {
+                templates.add(l);
+            }
+            templates
+        });
+        let baseline =
+            log_line_histogram::summarize(&baseline_lines, templates.as_ref(), Option::None);
+        let candidate = log_line_histogram::summarize(lines, templates.as_ref(), Option::None);
+        histogram_diff::write_diff(&baseline, &candidate, out, args.format);
+    }
+}
+
+// Write the histogram diff of the baseline and the histogram of the input in
+// streaming mode, where the baseline is streamed too
+fn write_streaming_histogram_diff(args: &Cli, histogram: &StreamingHistogram) {
+    if let Some(out) = &args.histogram_diff_file {
+        let inputs = NodeInputs::new("", &args.baseline_log_files);
+        let (baseline, candidate) = histogram.diff_summaries(|baseline| {
+            for_each_baseline_line(args, &inputs, |l| baseline.add(l));
+        });
+        histogram_diff::write_diff(&baseline, &candidate, out, args.format);
+    }
+}
+
+// Write the job latency diff of the baseline and the input
+fn write_latency_diff(args: &Cli, candidate: &JobLatencyStats) {
+    if let Some(out) = &args.job_latency_diff_file {
+        let baseline = baseline_latency(args);
+        job_latency_diff::write_diff(&baseline, candidate, out, args.format, args.tolerance);
+    }
+}
+
+// Write the result of the job latency checks, and exit if any fail
+fn check_slo(slo: &mut JobLatencySlo) {
+    if !slo.check(&mut std::io::stdout()) {
+        std::process::exit(2);
+    }
+}
+
+// Analyze the log one line at a time. Only the histogram keeps any state, and
+// only a count and an example line for every group.
+fn analyze_streaming(
+    args: &Cli,
+    nodes: &[NodeInputs],
+    time_range: &Option<TimeRange>,
+    rejects: Rejects,
+    mut slo: Option<JobLatencySlo>,
+    stats_spec: Option<StatsSpec>,
+) {
+    let rejects = RefCell::new(rejects);
+    let rejects_ref = &rejects;
+
+    // Merge the logs from different nodes by time
+    let streams = nodes.iter().map(|n| {
+        n.inputs
+            .iter()
+            .flat_map(move |input| parse::stream_input(input, &n.label, time_range, rejects_ref))
+    });
+    let lines = itertools::kmerge_by(streams, |a: &LogLine, b: &LogLine| a.time < b.time);
+
+    let mut json = args
+        .json_file
+        .as_ref()
+        .map(|out| JsonWriter::new(out, args.mixed_json));
+    let mut latency = (args.job_latency_file.is_some() || args.job_latency_diff_file.is_some())
+        .then(|| JobLatencyStats::new(args.sub_bucket_bits));
+    let mut series = args
+        .job_latency_series_file
+        .as_ref()
+        .map(|_| JobLatencySeries::new(args.interval));
+    let mut queue = args
+        .job_queue_file
+        .as_ref()
+        .map(|_| JobQueue::new(args.interval));
+    let mut stats = stats_spec.map(|spec| FieldStats::new(spec, args.sub_bucket_bits));
+    let mut jlog_report = args.jlog_report_file.as_ref().map(|_| JlogIdReport::new());
+    let mut histogram = (args.histogram_file.is_some()
+        || args.histogram_diff_file.is_some()
+        || args.group_rates_file.is_some())
+    .then(|| streaming_histogram(args));
+
+    for l in lines {
+        if let Some(range) = time_range {
+            if !range.contains(l.time) {
+                continue;
+            }
+        }
+        if let Some(filter) = &args.filter {
+            if !filter.matches(&l) {
+                continue;
+            }
+        }
+        if let Some(json) = json.as_mut() {
+            json.write(&l);
+        }
+        if let Some(latency) = latency.as_mut() {
+            latency.add(&l);
+        }
+        if let Some(series) = series.as_mut() {
+            series.add(&l);
+        }
+        if let Some(queue) = queue.as_mut() {
+            queue.add(&l);
+        }
+        if let Some(slo) = slo.as_mut() {
+            slo.add(&l);
+        }
+        if let Some(stats) = stats.as_mut() {
+            stats.add(&l);
+        }
+        if let Some(jlog_report) = jlog_report.as_mut() {
+            jlog_report.add(&l);
+        }
+        if let Some(histogram) = histogram.as_mut() {
+            histogram.add(&l);
+        }
+    }
+
+    rejects.into_inner().report();
+
+    if let Some(mut json) = json {
+        json.finish();
+    }
+    if let Some(mut latency) = latency {
+        if let Some(out) = &args.job_latency_file {
+            latency.write(out, args.format);
+        }
+        write_latency_diff(args, &latency);
+    }
+    if let (Some(mut series), Some(out)) = (series, &args.job_latency_series_file) {
+        series.write(out, args.format);
+    }
+    if let (Some(mut queue), Some(out)) = (queue, &args.job_queue_file) {
+        queue.write(out, args.format);
+    }
+    if let (Some(stats), Some(out)) = (stats, &args.stats_file) {
+        stats.write(out, args.format);
+    }
+    if let (Some(jlog_report), Some(out)) = (jlog_report, &args.jlog_report_file) {
+        jlog_report.write(out, args.format);
+    }
+    if let Some(mut slo) = slo {
+        check_slo(&mut slo);
+    }
+    if let Some(histogram) = histogram {
+        write_streaming_histogram_diff(args, &histogram);
+        if let Some(out) = &args.group_rates_file {
+            group_rates::write_rates(&histogram.summary(), out, args.format);
+        }
+        if let Some(out) = &args.histogram_file {
+            histogram.write(out, args.histogram_format);
+        }
+    }
+}
+
+// Analyze the log as it is written. Lines are written to the json file as they
+// are read, and the job latency stats, series and field stats are rewritten
+// every `follow_interval`.
+// This runs until the program is killed.
+// Rotation is noticed by the inode of the file, so follow mode is only
+// available on unix.
+#[cfg(unix)]
+fn follow(args: &Cli, mut rejects: Rejects, stats_spec: Option<StatsSpec>) {
+    let path = &args.input_log_files[0];
+    let mut follower = match Follower::new(path) {
+        Ok(follower) => follower,
+        Err(why) => {
+            eprintln!("Couldn't open {}: {}", path.display(), why);
+            std::process::exit(1);
+        }
+    };
+
+    let mut json = args
+        .json_file
+        .as_ref()
+        .map(|out| JsonWriter::new(out, args.mixed_json));
+    let mut latency = args
+        .job_latency_file
+        .as_ref()
+        .map(|_| JobLatencyStats::new(args.sub_bucket_bits));
+    let mut series = args
+        .job_latency_series_file
+        .as_ref()
+        .map(|_| JobLatencySeries::new(args.interval));
+    let mut stats = stats_spec.map(|spec| FieldStats::new(spec, args.sub_bucket_bits));
+
+    let interval = args.follow_interval.to_std().unwrap();
+    let mut last_update: Option<Instant> = Option::None;
+    loop {
+        let got_data = follower.poll(&mut rejects, |l| {
+            if let Some(filter) = &args.filter {
+                if !filter.matches(l) {
+                    return;
+                }
+            }
+            if let Some(json) = json.as_mut() {
+                json.write(l);
+            }
+            if let Some(latency) = latency.as_mut() {
+                latency.add(l);
+            }
+            if let Some(series) = series.as_mut() {
+                series.add(l);
+            }
+            if let Some(stats) = stats.as_mut() {
+                stats.add(l);
+            }
+        });
+        if let Err(why) = got_data {
+            eprintln!("Couldn't read {}: {}", path.display(), why);
+            std::process::exit(1);
+        }
+
+        if let Some(json) = json.as_mut() {
+            json.finish();
+        }
+        if last_update.is_none_or(|t| t.elapsed() >= interval) {
+            if let (Some(latency), Some(out)) = (latency.as_mut(), &args.job_latency_file) {
+                latency.write(out, args.format);
+            }
+            if let (Some(series), Some(out)) = (series.as_mut(), &args.job_latency_series_file) {
+                series.write(out, args.format);
+            }
+            if let (Some(stats), Some(out)) = (stats.as_mut(), &args.stats_file) {
+                stats.write(out, args.format);
+            }
+            rejects.report();
+            last_update = Some(Instant::now());
+        }
+
+        if !got_data.unwrap() {
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+// Exit if an option is given that follow mode doesn't support
+#[cfg(unix)]
+fn check_follow_args(args: &Cli) {
+    if args.input_log_files.len() != 1 || !args.nodes.is_empty() {
+        eprintln!("Follow mode needs exactly one input file");
+        std::process::exit(1);
+    }
+    // the file is followed by its path, so it can't be a pattern
+    if is_pattern(&args.input_log_files[0]) {
+        eprintln!(
+            "Follow mode needs a file, not a pattern: {}",
+            args.input_log_files[0].display()
+        );
+        std::process::exit(1);
+    }
+    // compressed files are decompressed as a whole, so they can't be followed
+    if is_compressed(&args.input_log_files[0]) {
+        eprintln!(
+            "Follow mode needs an uncompressed file: {}",
+            args.input_log_files[0].display()
+        );
+        std::process::exit(1);
+    }
+    if args.templates && args.json_file.is_some() {
+        eprintln!("Template ids can't be added to the json output in streaming or follow mode");
+        std::process::exit(1);
+    }
+    if args.histogram_file.is_some()
+        || args.grouped_file.is_some()
+        || args.correlate_file.is_some()
+        || args.histogram_diff_file.is_some()
+        || args.group_rates_file.is_some()
+    {
+        eprintln!(
+            "The histogram, grouped output, correlation, histogram diff and group rates can't be written in follow mode"
+        );
+        std::process::exit(1);
+    }
+    if args.since.is_some() || args.until.is_some() {
+        eprintln!("--since and --until can't be used in follow mode");
+        std::process::exit(1);
+    }
+    if args.job_latency_slo_file.is_some()
+        || args.job_latency_diff_file.is_some()
+        || args.job_queue_file.is_some()
+        || args.jlog_report_file.is_some()
+    {
+        eprintln!(
+            "The job latency checks, diff, job queue and jlogId report can't be run in follow mode"
+        );
+        std::process::exit(1);
+    }
 }
 
 fn main() {
     let args = Cli::from_args();
 
-    if args.histogram_file.is_none() && args.json_file.is_none() {
+    if args.histogram_file.is_none()
+        && args.json_file.is_none()
+        && args.grouped_file.is_none()
+        && args.job_latency_file.is_none()
+        && args.job_latency_series_file.is_none()
+        && args.job_latency_slo_file.is_none()
+        && args.job_latency_diff_file.is_none()
+        && args.job_queue_file.is_none()
+        && args.correlate_file.is_none()
+        && args.stats_file.is_none()
+        && args.jlog_report_file.is_none()
+        && args.histogram_diff_file.is_none()
+        && args.group_rates_file.is_none()
+    {
         eprintln!("Must specify at least one output file");
         std::process::exit(1);
     }
 
-    let file = match memmap_log::MemmapLog::new(&args.input_log_file) {
-        Err(why) => panic!(
-            "Couldn't open {}: {}",
-            args.input_log_file.display(),
-            Error::to_string(&why)
-        ),
-        Ok(file) => file,
+    let stats_spec = stats_spec(&args);
+
+    if args.stream && (args.grouped_file.is_some() || args.correlate_file.is_some()) {
+        eprintln!("The grouped output and correlation can't be written in streaming mode");
+        std::process::exit(1);
+    }
+
+    // The template of a line is only known once all the lines are mined
+    if args.stream && args.templates && args.json_file.is_some() {
+        eprintln!("Template ids can't be added to the json output in streaming or follow mode");
+        std::process::exit(1);
+    }
+
+    #[cfg(unix)]
+    if args.follow {
+        check_follow_args(&args);
+        follow(&args, Rejects::new(&args.reject_file), stats_spec);
+        return;
+    }
+    if args.threads > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let mut nodes = Vec::new();
+    if !args.input_log_files.is_empty() {
+        nodes.push(NodeInputs::new("", &args.input_log_files));
+    }
+    for n in &args.nodes {
+        nodes.push(NodeInputs::new(&n.label, std::slice::from_ref(&n.path)));
+    }
+
+    let mut rejects = Rejects::new(&args.reject_file);
+
+    // Read the checks before the log, so a bad config is reported right away
+    let mut slo = args.job_latency_slo_file.as_ref().map(JobLatencySlo::new);
+
+    let time_range = TimeRange::new(
+        nodes.iter().filter_map(|n| n.first_time()).min(),
+        nodes.iter().filter_map(|n| n.last_time()).max(),
+        &args.since,
+        &args.until,
+    );
+
+    if args.stream {
+        analyze_streaming(&args, &nodes, &time_range, rejects, slo, stats_spec);
+        return;
+    }
+
+    let mut node_lines: Vec<Vec<LogLine>> = nodes
+        .iter()
+        .map(|n| {
+            let mut lines = Vec::<LogLine>::with_capacity(1024 * 1024);
+            for input in &n.inputs {
+                parse::parse_input(
+                    input,
+                    &n.label,
+                    &time_range,
+                    args.threads != 1,
+                    &mut rejects,
+                    &mut lines,
+                );
+            }
+            lines
+        })
+        .collect();
+
+    for lines in node_lines.iter_mut() {
+        // The log may not be perfectly sorted near the ends of the range
+        if let Some(range) = &time_range {
+            lines.retain(|l| range.contains(l.time));
+        }
+        if let Some(filter) = &args.filter {
+            lines.retain(|l| filter.matches(l));
+        }
+    }
+
+    rejects.report();
+
+    // The latencies and field statistics don't depend on the order of the
+    // lines, so they're collected for every node in parallel and merged
+    let latency =
+        (args.job_latency_file.is_some() || args.job_latency_diff_file.is_some()).then(|| {
+            node_lines
+                .par_iter()
+                .map(|lines| {
+                    let mut latency = JobLatencyStats::new(args.sub_bucket_bits);
+                    for l in lines {
+                        latency.add(l);
+                    }
+                    latency
+                })
+                .reduce(
+                    || JobLatencyStats::new(args.sub_bucket_bits),
+                    |mut a, b| {
+                        a.merge(b);
+                        a
+                    },
+                )
+        });
+    let stats = args.stats_file.as_ref().and(stats_spec).map(|spec| {
+        node_lines
+            .par_iter()
+            .map(|lines| {
+                let mut stats = FieldStats::new(spec.clone(), args.sub_bucket_bits);
+                for l in lines {
+                    stats.add(l);
+                }
+                stats
+            })
+            .reduce(
+                || FieldStats::new(spec.clone(), args.sub_bucket_bits),
+                |mut a, b| {
+                    a.merge(b);
+                    a
+                },
+            )
+    });
+
+    // Merge the logs from different nodes by time
+    let lines_vec: Vec<LogLine> = if node_lines.len() == 1 {
+        node_lines.pop().unwrap()
+    } else {
+        node_lines
+            .into_iter()
+            .kmerge_by(|a, b| a.time < b.time)
+            .collect()
     };
 
-    let mut lines_vec = Vec::<LogLine>::with_capacity(1024 * 1024);
+    let templates = args.templates.then(|| {
+        let mut templates = TemplateMiner::new();
+        for l in &lines_vec {
+            templates.add(l);
+        }
+        templates
+    });
+
+    if let Some(out) = &args.json_file {
+        to_json::to_json(&lines_vec, out, args.mixed_json, templates.as_ref());
+    }
+
+    if let Some(mut latency) = latency {
+        if let Some(out) = &args.job_latency_file {
+            latency.write(out, args.format);
+        }
+        write_latency_diff(&args, &latency);
+    }
+
+    if let Some(out) = &args.job_latency_series_file {
+        let mut series = JobLatencySeries::new(args.interval);
+        for l in &lines_vec {
+            series.add(l);
+        }
+        series.write(out, args.format);
+    }
 
-    for l in file.as_str().lines() {
-        if let Some(log_line) = LogLine::new(&l) {
-            lines_vec.push(log_line)
+    if let Some(out) = &args.job_queue_file {
+        let mut queue = JobQueue::new(args.interval);
+        for l in &lines_vec {
+            queue.add(l);
         }
+        queue.write(out, args.format);
     }
 
-    if let Some(out) = args.json_file {
-        to_json::to_json(&lines_vec, &out, args.mixed_json);
+    if let (Some(stats), Some(out)) = (stats, &args.stats_file) {
+        stats.write(out, args.format);
     }
 
-    if let Some(out) = args.job_latency_file {
-        job_latency::job_latency_stats(&lines_vec, &out);
+    if let Some(out) = &args.jlog_report_file {
+        let mut jlog_report = JlogIdReport::new();
+        for l in &lines_vec {
+            jlog_report.add(l);
+        }
+        jlog_report.write(out, args.format);
+    }
+
+    if let Some(slo) = slo.as_mut() {
+        for l in &lines_vec {
+            slo.add(l);
+        }
     }
 
-    if args.histogram_file.is_some() || args.grouped_file.is_some() {
+    if args.histogram_file.is_some()
+        || args.grouped_file.is_some()
+        || args.correlate_file.is_some()
+        || args.histogram_diff_file.is_some()
+        || args.group_rates_file.is_some()
+    {
         let lines_set: BTreeSet<LogLine> = lines_vec.into_iter().collect();
-        log_line_histogram::to_histogram(&lines_set, &args.histogram_file, &args.grouped_file);
+        if args.histogram_file.is_some() || args.grouped_file.is_some() {
+            log_line_histogram::to_histogram(
+                &lines_set,
+                &args.histogram_file,
+                &args.grouped_file,
+                templates.as_ref(),
+                args.histogram_format,
+            );
+        }
+        write_histogram_diff(&args, &lines_set);
+        if let Some(out) = &args.group_rates_file {
+            let summary =
+                log_line_histogram::summarize(&lines_set, templates.as_ref(), Some(args.interval));
+            group_rates::write_rates(&summary, out, args.format);
+        }
+        if let Some(out) = &args.correlate_file {
+            let spikes = Spikes::new(args.spike_threshold, args.spike_job.clone(), args.window);
+            spikes.write_correlation(&lines_set, templates.as_ref(), out);
+        }
+    }
+
+    if let Some(mut slo) = slo {
+        check_slo(&mut slo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_time_bounds_parse_from_command_line() {
+        let args = Cli::from_iter_safe(&[
+            "rs-log-analyzer",
+            "-i",
+            "debug.log",
+            "--since",
+            "-10m",
+            "--until",
+            "-1m30s",
+        ])
+        .unwrap();
+        match (args.since, args.until) {
+            (Some(TimeBound::FromLast(since)), Some(TimeBound::FromLast(until))) => {
+                assert_eq!(since, chrono::Duration::minutes(-10));
+                assert_eq!(until, chrono::Duration::seconds(-90));
+            }
+            _ => panic!("expected bounds relative to the last line"),
+        }
+
+        let args =
+            Cli::from_iter_safe(&["rs-log-analyzer", "-i", "debug.log", "--since", "+5m"]).unwrap();
+        assert!(matches!(args.since, Some(TimeBound::FromFirst(_))));
     }
 }
@@ -0,0 +1,212 @@
+// Parse log records into `LogLine`s
+
+// Parsing dominates the run time on large logs. `LogLine::new` only looks at
+// one record at a time, so a large log is split into chunks at record
+// boundaries, the chunks are parsed in parallel, and the results are
+// concatenated in order. The result is the same as parsing the log on a single
+// thread.
+
+use rayon::prelude::*;
+
+use std::cell::RefCell;
+
+use crate::log_input::LogInput;
+use crate::log_line::{LogLine, ParseError};
+use crate::log_record::{count_lines, split_records, LogRecord, LogRecords};
+use crate::rejects::Rejects;
+use crate::time_range::TimeRange;
+
+// Logs smaller than this are always parsed on one thread
+const MIN_PARALLEL_SIZE: usize = 1024 * 1024;
+
+#[derive(Default)]
+pub struct Parsed<'a> {
+    pub lines: Vec<LogLine<'a>>,
+    pub rejected: Vec<(LogRecord<'a>, ParseError)>,
+}
+
+fn parse_records<'a>(records: LogRecords<'a>, parsed: &mut Parsed<'a>) {
+    for r in records {
+        match LogLine::new(r.text) {
+            Ok(log_line) => parsed.lines.push(log_line),
+            Err(err) => parsed.rejected.push((r, err)),
+        }
+    }
+}
+
+// Parse the records in `buf[start..end]` on the current thread
+pub fn parse_sequential(buf: &str, start: usize, end: usize) -> Parsed<'_> {
+    let mut parsed = Parsed::default();
+    parse_records(LogRecords::range(buf, start, end), &mut parsed);
+    parsed
+}
+
+// Parse the records in `buf[start..end]` on all the threads in the rayon thread pool
+pub fn parse_parallel(buf: &str, start: usize, end: usize) -> Parsed<'_> {
+    if end - start < MIN_PARALLEL_SIZE {
+        return parse_sequential(buf, start, end);
+    }
+
+    // Use more chunks than threads so a slow chunk doesn't hold up the others
+    let chunks = split_records(buf, start, end, rayon::current_num_threads() * 4);
+
+    // The line number at the start of each chunk
+    let mut line_numbers: Vec<usize> = chunks
+        .par_iter()
+        .map(|(s, e)| count_lines(&buf[*s..*e]))
+        .collect();
+    let mut line_number = 1 + count_lines(&buf[..start]);
+    for n in line_numbers.iter_mut() {
+        let lines_in_chunk = *n;
+        *n = line_number;
+        line_number += lines_in_chunk;
+    }
+
+    let parsed_chunks: Vec<Parsed> = chunks
+        .par_iter()
+        .zip(line_numbers.par_iter())
+        .map(|((s, e), line_number)| {
+            let mut parsed = Parsed {
+                lines: Vec::with_capacity((e - s) / 128),
+                rejected: Vec::new(),
+            };
+            parse_records(
+                LogRecords::with_line_number(buf, *s, *e, *line_number),
+                &mut parsed,
+            );
+            parsed
+        })
+        .collect();
+
+    let mut result = Parsed {
+        lines: Vec::with_capacity(parsed_chunks.iter().map(|p| p.lines.len()).sum()),
+        rejected: Vec::new(),
+    };
+    for p in parsed_chunks {
+        result.lines.extend(p.lines);
+        result.rejected.extend(p.rejected);
+    }
+    result
+}
+
+// Parse the records of `input` that may be in the time range and add them to `lines`
+pub fn parse_input<'a>(
+    input: &'a LogInput,
+    node: &'a str,
+    time_range: &Option<TimeRange>,
+    parallel: bool,
+    rejects: &mut Rejects,
+    lines: &mut Vec<LogLine<'a>>,
+) {
+    let buf = input.as_str();
+    let (start, end) = match time_range {
+        Some(range) => range.offsets(buf),
+        None => (0, buf.len()),
+    };
+
+    let parsed = if parallel {
+        parse_parallel(buf, start, end)
+    } else {
+        parse_sequential(buf, start, end)
+    };
+
+    for (r, err) in &parsed.rejected {
+        rejects.add(&input.path, r, err);
+    }
+    lines.extend(parsed.lines.into_iter().map(|mut l| {
+        l.node = node;
+        l
+    }));
+}
+
+// Parse the records of `input` that may be in the time range one at a time
+pub fn stream_input<'a, 'r>(
+    input: &'a LogInput,
+    node: &'a str,
+    time_range: &Option<TimeRange>,
+    rejects: &'r RefCell<Rejects>,
+) -> impl Iterator<Item = LogLine<'a>> + 'r
+where
+    'a: 'r,
+{
+    let buf = input.as_str();
+    let (start, end) = match time_range {
+        Some(range) => range.offsets(buf),
+        None => (0, buf.len()),
+    };
+    LogRecords::range(buf, start, end).filter_map(move |r| match LogLine::new(r.text) {
+        Ok(mut log_line) => {
+            log_line.node = node;
+            Some(log_line)
+        }
+        Err(err) => {
+            rejects.borrow_mut().add(&input.path, &r, &err);
+            Option::None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A log over `MIN_PARALLEL_SIZE` with multi-line records and lines that
+    // are rejected, so the chunks split it in many places
+    fn log() -> String {
+        let mut log = String::new();
+        let mut i = 0;
+        while log.len() < 2 * MIN_PARALLEL_SIZE {
+            let ts = format!(
+                "2021-Feb-03 17:{:02}:{:02}.{:09} UTC",
+                (i / 60) % 60,
+                i % 60,
+                i
+            );
+            log.push_str(&format!("{} Peer:NFO Peer {} connected\n", ts, i));
+            log.push_str(&format!(
+                "{} LedgerConsensus:WRN View of consensus changed during open\n{} LedgerConsensus:WRN {:064X}\n",
+                ts, ts, i
+            ));
+            log.push_str(&format!(
+                "{} LedgerHistory:ERR valid\nno timestamp {}\n\n",
+                ts, i
+            ));
+            if i % 7 == 0 {
+                log.push_str(&format!("{} Peer:BAD bad level\n", ts));
+            }
+            i += 1;
+        }
+        log
+    }
+
+    #[test]
+    fn parse_parallel_matches_parse_sequential() {
+        let log = log();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap();
+        let start = record_start_after(&log, 1000);
+        for (start, end) in [(0, log.len()), (start, log.len())] {
+            let sequential = parse_sequential(&log, start, end);
+            let parallel = pool.install(|| parse_parallel(&log, start, end));
+            assert!(!sequential.rejected.is_empty());
+            assert_eq!(parallel.lines, sequential.lines);
+            let rejected = |p: &Parsed| -> Vec<(usize, usize, String)> {
+                p.rejected
+                    .iter()
+                    .map(|(r, _)| (r.offset, r.line_number, r.text.to_string()))
+                    .collect()
+            };
+            assert_eq!(rejected(&parallel), rejected(&sequential));
+        }
+    }
+
+    // The start of the first record after `pos`
+    fn record_start_after(log: &str, pos: usize) -> usize {
+        LogRecords::range(log, 0, log.len())
+            .map(|r| r.offset)
+            .find(|offset| *offset >= pos)
+            .unwrap()
+    }
+}
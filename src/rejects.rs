@@ -0,0 +1,81 @@
+// Log records that could not be parsed
+
+// A corrupted or truncated line should not stop the analysis of the rest of
+// the log. Rejected records are counted, and if a reject file was given, they
+// are written to it with their line number and byte offset so they can be
+// found in the original log.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::log_line::ParseError;
+use crate::log_record::LogRecord;
+
+pub struct Rejects {
+    out_file: Option<BufWriter<std::fs::File>>,
+    malformed: usize,
+    bad_timestamp: usize,
+    bad_level: usize,
+}
+
+impl Rejects {
+    pub fn new(out_file_name: &Option<std::path::PathBuf>) -> Self {
+        let out_file = out_file_name
+            .as_ref()
+            .map(|fname| match std::fs::File::create(fname) {
+                Ok(file) => BufWriter::new(file),
+                _ => {
+                    eprintln!("Could not create file {} in rejects", fname.display());
+                    std::process::exit(1);
+                }
+            });
+        Rejects {
+            out_file,
+            malformed: 0,
+            bad_timestamp: 0,
+            bad_level: 0,
+        }
+    }
+
+    pub fn add(&mut self, path: &Path, record: &LogRecord, err: &ParseError) {
+        match err {
+            ParseError::Malformed => self.malformed += 1,
+            ParseError::BadTimestamp(_) => self.bad_timestamp += 1,
+            ParseError::BadLevel(_) => self.bad_level += 1,
+        }
+        if let Some(out) = self.out_file.as_mut() {
+            writeln!(
+                out,
+                "{} line {} offset {}: {}\n{}",
+                path.display(),
+                record.line_number,
+                record.offset,
+                err,
+                record.text
+            )
+            .unwrap();
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.malformed + self.bad_timestamp + self.bad_level
+    }
+
+    // Write a summary of the rejected records to stderr, and flush the reject
+    // file so it is complete even if the program exits or is killed afterwards
+    pub fn report(&mut self) {
+        if let Some(out) = self.out_file.as_mut() {
+            out.flush().unwrap();
+        }
+        if self.count() == 0 {
+            return;
+        }
+        eprintln!(
+            "Rejected {} log records: {} malformed, {} bad timestamps, {} bad log levels",
+            self.count(),
+            self.malformed,
+            self.bad_timestamp,
+            self.bad_level
+        );
+    }
+}
@@ -0,0 +1,54 @@
+// Format of the reports written by the analyzer
+
+// The text format is for people. The json and csv formats are for dashboards
+// and scripts, so they contain the same numbers without any decoration.
+
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("Expected text, json or csv, found {}", s)),
+        }
+    }
+}
+
+// Quote a value of a csv file, such as a job or group name, which may contain
+// commas or quotes
+pub fn csv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+// Format of the histogram and grouped outputs. The json format is an array of
+// the groups, and the ndjson format has one group per line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HistogramFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+impl FromStr for HistogramFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(HistogramFormat::Text),
+            "json" => Ok(HistogramFormat::Json),
+            "ndjson" => Ok(HistogramFormat::Ndjson),
+            _ => Err(format!("Expected text, json or ndjson, found {}", s)),
+        }
+    }
+}
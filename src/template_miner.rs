@@ -0,0 +1,318 @@
+// Mine log templates from the messages, like Drain
+
+// Drain (He et al., "Drain: An Online Log Parsing Approach with Fixed Depth
+// Tree", 2017) finds the template of a message in a tree of fixed depth. The
+// first level of the tree is the level, module and number of words of the
+// message. The next `TOKEN_DEPTH` levels are the first words of the message,
+// where words with numbers, and words past `MAX_CHILDREN` different words, go
+// to the `<*>` child. The leaves hold the templates, and a message is merged
+// into the most similar template of its leaf if enough of its words are the
+// same; the words that differ become `<*>`. For example:
+//
+// LedgerCache target age set to 180000000000
+// LedgerCache target age set to 120
+//
+// both have the template "LedgerCache target age set to <*>".
+//
+// A template id is a hash of the level, module and template text, so the same
+// template text has the same id in every run and in every log. Templates
+// mined from one log at a time may still differ between logs when a word varies
+// in only one of them, so the histogram diff mines the templates of both logs
+// with one miner.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::log_line::{LogLevel, LogLine};
+
+// Number of words of the message that are levels of the tree. Drain uses 2, but
+// the second word of a message is often a variable, like a peer or job name.
+const TOKEN_DEPTH: usize = 1;
+// Most children of a node of the tree, including `<*>`
+const MAX_CHILDREN: usize = 100;
+// Share of the words that must be the same to merge a message into a template
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+const WILDCARD: &str = "<*>";
+
+pub struct Template {
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    clusters: Vec<usize>, // index in `TemplateMiner::clusters`, only in leaves
+}
+
+#[derive(Clone)]
+struct Cluster {
+    level: LogLevel,
+    module: String,
+    tokens: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct TemplateMiner {
+    // keyed by level, module, structured, and number of words
+    roots: BTreeMap<(LogLevel, String, bool, usize), Node>,
+    clusters: Vec<Cluster>,
+}
+
+// The words of the message. Words with numbers are variables, like `n_prefix`
+// in the histogram treats them.
+fn tokens(msg: &str) -> Vec<&str> {
+    msg.split(' ')
+        .map(|w| {
+            if w.chars().any(|c| c.is_numeric()) {
+                WILDCARD
+            } else {
+                w
+            }
+        })
+        .collect()
+}
+
+// Share of the words of `tokens` that are the same in `template`, and the
+// number of wildcards in the template
+fn similarity(template: &[String], tokens: &[&str]) -> (f64, usize) {
+    if tokens.is_empty() {
+        return (1.0, 0);
+    }
+    let mut same = 0;
+    let mut wildcards = 0;
+    for (t, w) in template.iter().zip(tokens) {
+        if t == WILDCARD {
+            wildcards += 1;
+        } else if t == w {
+            same += 1;
+        }
+    }
+    (same as f64 / tokens.len() as f64, wildcards)
+}
+
+// 64 bit FNV-1a, which unlike the std hasher is the same in every build
+fn fnv1a(s: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in s.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h
+}
+
+impl TemplateMiner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn root_key(l: &LogLine, n_tokens: usize) -> (LogLevel, String, bool, usize) {
+        (
+            l.level,
+            l.module.to_string(),
+            !l.json_data.is_empty(),
+            n_tokens,
+        )
+    }
+
+    // Add the message of the line, and return the index of its template
+    pub fn add(&mut self, l: &LogLine) -> usize {
+        let tokens = tokens(l.msg);
+        let mut node = self
+            .roots
+            .entry(Self::root_key(l, tokens.len()))
+            .or_default();
+        for t in tokens.iter().take(TOKEN_DEPTH) {
+            let child = if node.children.contains_key(*t)
+                || (*t != WILDCARD && node.children.len() + 1 < MAX_CHILDREN)
+            {
+                *t
+            } else {
+                WILDCARD
+            };
+            node = node.children.entry(child.to_string()).or_default();
+        }
+
+        let clusters = &mut self.clusters;
+        let best = node
+            .clusters
+            .iter()
+            .map(|i| (*i, similarity(&clusters[*i].tokens, &tokens)))
+            .filter(|(_, (sim, _))| *sim >= SIMILARITY_THRESHOLD)
+            // most similar, then fewest wildcards, then oldest
+            .max_by(|(a, (a_sim, a_wild)), (b, (b_sim, b_wild))| {
+                a_sim
+                    .total_cmp(b_sim)
+                    .then(b_wild.cmp(a_wild))
+                    .then(b.cmp(a))
+            })
+            .map(|(i, _)| i);
+
+        match best {
+            Some(i) => {
+                for (t, w) in clusters[i].tokens.iter_mut().zip(&tokens) {
+                    if t != w {
+                        *t = WILDCARD.to_string();
+                    }
+                }
+                i
+            }
+            None => {
+                clusters.push(Cluster {
+                    level: l.level,
+                    module: l.module.to_string(),
+                    tokens: tokens.iter().map(|t| t.to_string()).collect(),
+                });
+                node.clusters.push(clusters.len() - 1);
+                clusters.len() - 1
+            }
+        }
+    }
+
+    // The index of the template of a line that was added, without changing
+    // any template. Of the templates that match the message, the one with the
+    // fewest wildcards.
+    pub fn find(&self, l: &LogLine) -> Option<usize> {
+        let tokens = tokens(l.msg);
+        let mut node = self.roots.get(&Self::root_key(l, tokens.len()))?;
+        for t in tokens.iter().take(TOKEN_DEPTH) {
+            node = node
+                .children
+                .get(*t)
+                .or_else(|| node.children.get(WILDCARD))?;
+        }
+        node.clusters
+            .iter()
+            .filter(|i| {
+                self.clusters[**i]
+                    .tokens
+                    .iter()
+                    .zip(&tokens)
+                    .all(|(t, w)| t == WILDCARD || t == w)
+            })
+            .min_by_key(|i| similarity(&self.clusters[**i].tokens, &tokens).1)
+            .copied()
+    }
+
+    pub fn template(&self, i: usize) -> Template {
+        let c = &self.clusters[i];
+        let text = c.tokens.join(" ");
+        let id = fnv1a(&format!("{:?}:{}:{}", c.level, c.module, text));
+        Template {
+            id: format!("{:016x}", id),
+            text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(s: &str) -> LogLine<'_> {
+        LogLine::new(s).unwrap()
+    }
+
+    #[test]
+    fn similar_messages_share_a_template() {
+        let mut miner = TemplateMiner::new();
+        let a = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC TaggedCache:DBG LedgerCache target age set to 180000000000",
+        ));
+        let b = miner.add(&line(
+            "2021-Feb-05 13:52:55.660065778 UTC TaggedCache:DBG LedgerCache target age set to 120",
+        ));
+        assert_eq!(a, b);
+        assert_eq!(miner.template(a).text, "LedgerCache target age set to <*>");
+
+        // words that differ become wildcards
+        let c = miner.add(&line(
+            "2021-Feb-05 13:52:56.660065778 UTC TaggedCache:DBG LedgerCache target size set to 120",
+        ));
+        assert_eq!(a, c);
+        assert_eq!(miner.template(a).text, "LedgerCache target <*> set to <*>");
+    }
+
+    #[test]
+    fn different_messages_get_different_templates() {
+        let mut miner = TemplateMiner::new();
+        let a = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer disconnected from the network",
+        ));
+        // different module, level, and number of words
+        let b = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Overlay:WRN Peer disconnected from the network",
+        ));
+        let c = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:ERR Peer disconnected from the network",
+        ));
+        let d = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer disconnected",
+        ));
+        // too few words in common
+        let e = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer sent us a bad ledger",
+        ));
+        let all = [a, b, c, d, e];
+        for (i, x) in all.iter().enumerate() {
+            for y in &all[i + 1..] {
+                assert_ne!(x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn find_does_not_change_templates() {
+        let mut miner = TemplateMiner::new();
+        let a = miner.add(&line(
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer 12 disconnected",
+        ));
+        assert_eq!(
+            miner.find(&line(
+                "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer 99 disconnected"
+            )),
+            Some(a)
+        );
+        assert_eq!(
+            miner.find(&line(
+                "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer 99 connected"
+            )),
+            Option::None
+        );
+        assert_eq!(miner.template(a).text, "Peer <*> disconnected");
+    }
+
+    #[test]
+    fn template_ids_are_stable() {
+        let lines = [
+            "2021-Feb-05 13:52:54.660065778 UTC Peer:WRN Peer 12 disconnected",
+            "2021-Feb-05 13:52:54.660065778 UTC Ledger:NFO Built ledger 5",
+        ];
+        let mut first = TemplateMiner::new();
+        let first_ids: Vec<String> = lines
+            .iter()
+            .map(|l| {
+                let i = first.add(&line(l));
+                first.template(i).id
+            })
+            .collect();
+        // the ids don't depend on the order the templates were mined in
+        let mut second = TemplateMiner::new();
+        let second_ids: Vec<String> = lines
+            .iter()
+            .rev()
+            .map(|l| {
+                let i = second.add(&line(l));
+                second.template(i).id
+            })
+            .collect();
+        assert_eq!(first_ids[0], second_ids[1]);
+        assert_eq!(first_ids[1], second_ids[0]);
+        assert_ne!(first_ids[0], first_ids[1]);
+        assert_eq!(first_ids[0].len(), 16);
+        assert_eq!(
+            first_ids[0],
+            format!("{:016x}", fnv1a("Warning:Peer:Peer <*> disconnected"))
+        );
+    }
+}
@@ -0,0 +1,298 @@
+// Restrict the analysis to a window of time
+
+// A time bound is either an absolute timestamp or an offset relative to the
+// first or last line of the log:
+//
+// 2021-Feb-13 22:15:20           absolute (a fraction of a second and " UTC" are optional)
+// 2021-02-13T22:15:20            absolute
+// first+10m  or  +10m            10 minutes after the first line
+// last-10m   or  -10m            10 minutes before the last line
+//
+// Durations are a sequence of numbers with the units `d`, `h`, `m`, `s`, or
+// `ms`, for example `1h30m`.
+
+use chrono::{Duration, NaiveDateTime};
+
+use std::str::FromStr;
+
+use crate::log_line::parse_timestamp;
+use crate::log_record::{line_start, next_record_start, record_start, starts_with_timestamp};
+
+#[derive(Clone, Copy, Debug)]
+pub enum TimeBound {
+    Absolute(NaiveDateTime),
+    FromFirst(Duration),
+    FromLast(Duration),
+}
+
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut result = Duration::zero();
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Option::None;
+    }
+    while !rest.is_empty() {
+        let num_end = rest.find(|c: char| !c.is_ascii_digit())?;
+        if num_end == 0 {
+            return Option::None;
+        }
+        let n: i64 = rest[..num_end].parse().ok()?;
+        rest = &rest[num_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        result = result
+            + match &rest[..unit_end] {
+                "d" => Duration::days(n),
+                "h" => Duration::hours(n),
+                "m" => Duration::minutes(n),
+                "s" => Duration::seconds(n),
+                "ms" => Duration::milliseconds(n),
+                _ => return Option::None,
+            };
+        rest = &rest[unit_end..];
+    }
+    Some(result)
+}
+
+// Parse a positive duration given on the command line
+pub fn duration_arg(s: &str) -> Result<Duration, String> {
+    match parse_duration(s) {
+        Some(d) if d > Duration::zero() => Ok(d),
+        _ => Err(format!("Bad duration: {}", s)),
+    }
+}
+
+fn parse_absolute(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    if let Some(t) = parse_timestamp(s) {
+        return Some(t);
+    }
+    let s = s.trim_end_matches(" UTC");
+    for format in &[
+        "%Y-%b-%d %H:%M:%S%.f",
+        "%Y-%b-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+    ] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(t);
+        }
+    }
+    Option::None
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Bad time: {}", s);
+        let signed_duration = |d: &str| -> Option<Duration> {
+            if let Some(d) = d.strip_prefix('+') {
+                parse_duration(d)
+            } else if let Some(d) = d.strip_prefix('-') {
+                parse_duration(d).map(|d| -d)
+            } else {
+                Option::None
+            }
+        };
+        if let Some(d) = s.strip_prefix("first") {
+            return signed_duration(d).map(TimeBound::FromFirst).ok_or_else(err);
+        }
+        if let Some(d) = s.strip_prefix("last") {
+            return signed_duration(d).map(TimeBound::FromLast).ok_or_else(err);
+        }
+        if s.starts_with('+') {
+            return signed_duration(s).map(TimeBound::FromFirst).ok_or_else(err);
+        }
+        if s.starts_with('-') {
+            return signed_duration(s).map(TimeBound::FromLast).ok_or_else(err);
+        }
+        parse_absolute(s).map(TimeBound::Absolute).ok_or_else(err)
+    }
+}
+
+impl TimeBound {
+    pub fn resolve(&self, first: NaiveDateTime, last: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            TimeBound::Absolute(t) => *t,
+            TimeBound::FromFirst(d) => first + *d,
+            TimeBound::FromLast(d) => last + *d,
+        }
+    }
+}
+
+fn line_time(line: &str) -> Option<NaiveDateTime> {
+    if !starts_with_timestamp(line) {
+        return Option::None;
+    }
+    let end = line.find(" UTC")? + 4;
+    parse_timestamp(&line[..end])
+}
+
+// The time of the first line at or after `pos` that has a timestamp
+fn first_time_from(buf: &str, pos: usize) -> Option<NaiveDateTime> {
+    let mut pos = line_start(buf, pos);
+    while pos < buf.len() {
+        let next = line_start(buf, pos + 1);
+        if let Some(t) = line_time(&buf[pos..next]) {
+            return Some(t);
+        }
+        pos = next;
+    }
+    Option::None
+}
+
+pub fn first_time(buf: &str) -> Option<NaiveDateTime> {
+    first_time_from(buf, 0)
+}
+
+pub fn last_time(buf: &str) -> Option<NaiveDateTime> {
+    buf.trim_end().lines().rev().find_map(line_time)
+}
+
+// Return the offset of the first record with a time at or after `t`. The time
+// of a record is the time of its first line. The log is assumed to be sorted by
+// time, so this only looks at a logarithmic number of lines.
+pub fn find_offset(buf: &str, t: NaiveDateTime) -> usize {
+    let mut lo = 0;
+    let mut hi = buf.len();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match first_time_from(buf, mid) {
+            Some(mid_time) if mid_time < t => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    let pos = line_start(buf, lo);
+    let start = record_start(buf, pos);
+    // `pos` may be a continuation line of a record that starts before `t`
+    match line_time(&buf[start..]) {
+        Some(start_time) if start_time < t => next_record_start(buf, line_start(buf, pos + 1)),
+        _ => start,
+    }
+}
+
+pub struct TimeRange {
+    pub since: NaiveDateTime,
+    pub until: NaiveDateTime,
+}
+
+impl TimeRange {
+    // `first` and `last` are the times of the first and last lines of the log.
+    // Return None if neither bound is given.
+    pub fn new(
+        first: Option<NaiveDateTime>,
+        last: Option<NaiveDateTime>,
+        since: &Option<TimeBound>,
+        until: &Option<TimeBound>,
+    ) -> Option<TimeRange> {
+        if since.is_none() && until.is_none() {
+            return Option::None;
+        }
+        let first = first.unwrap_or_else(|| NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
+        let last = last.unwrap_or(first);
+        Some(TimeRange {
+            since: since.map_or(first, |b| b.resolve(first, last)),
+            until: until.map_or(last, |b| b.resolve(first, last)),
+        })
+    }
+
+    pub fn contains(&self, t: NaiveDateTime) -> bool {
+        self.since <= t && t <= self.until
+    }
+
+    // The part of `buf` that may contain records in this range
+    pub fn offsets(&self, buf: &str) -> (usize, usize) {
+        let start = find_offset(buf, self.since);
+        let end = match self.until.checked_add_signed(Duration::nanoseconds(1)) {
+            Some(after) => find_offset(buf, after),
+            None => buf.len(),
+        };
+        (start, end.max(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+2021-Feb-13 22:15:20.000000000 UTC Peer:NFO one
+2021-Feb-13 22:15:21.000000000 UTC Peer:NFO two
+2021-Feb-13 22:15:21.000000000 UTC Peer:NFO two again
+2021-Feb-13 22:15:22.000000000 UTC LedgerHistory:ERR valid
+continuation without a timestamp
+2021-Feb-13 22:15:23.000000000 UTC LedgerConsensus:WRN View of consensus changed
+2021-Feb-13 22:15:23.500000000 UTC LedgerConsensus:WRN {\"accepted\":true}
+2021-Feb-13 22:15:25.000000000 UTC Peer:NFO last
+";
+
+    fn t(s: &str) -> NaiveDateTime {
+        parse_absolute(s).unwrap()
+    }
+
+    fn offset_of(line: &str) -> usize {
+        LOG.find(line).unwrap()
+    }
+
+    #[test]
+    fn find_offset_before_and_after_the_log() {
+        assert_eq!(find_offset(LOG, t("2021-Feb-13 22:00:00")), 0);
+        assert_eq!(find_offset(LOG, t("2021-Feb-13 22:15:20")), 0);
+        assert_eq!(find_offset(LOG, t("2021-Feb-13 22:16:00")), LOG.len());
+        assert_eq!(find_offset("", t("2021-Feb-13 22:15:20")), 0);
+    }
+
+    #[test]
+    fn find_offset_returns_the_first_of_equal_times() {
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:21")),
+            offset_of("2021-Feb-13 22:15:21")
+        );
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:20.5")),
+            offset_of("2021-Feb-13 22:15:21")
+        );
+    }
+
+    #[test]
+    fn find_offset_returns_the_start_of_a_record() {
+        // after the line without a timestamp, the next time is 22:15:23
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:22.5")),
+            offset_of("2021-Feb-13 22:15:23")
+        );
+        // the 22:15:23.5 line is part of the 22:15:23 record
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:23")),
+            offset_of("2021-Feb-13 22:15:23")
+        );
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:23.5")),
+            offset_of("2021-Feb-13 22:15:25")
+        );
+        assert_eq!(
+            find_offset(LOG, t("2021-Feb-13 22:15:25")),
+            offset_of("2021-Feb-13 22:15:25")
+        );
+    }
+
+    #[test]
+    fn time_range_offsets_include_the_until_time() {
+        let range = TimeRange::new(
+            first_time(LOG),
+            last_time(LOG),
+            &Some("2021-Feb-13 22:15:21".parse().unwrap()),
+            &Some("first+2s".parse().unwrap()),
+        )
+        .unwrap();
+        assert_eq!(
+            range.offsets(LOG),
+            (
+                offset_of("2021-Feb-13 22:15:21"),
+                offset_of("2021-Feb-13 22:15:23")
+            )
+        );
+    }
+}
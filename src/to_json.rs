@@ -1,46 +1,94 @@
 use crate::log_line::LogLine;
+use crate::template_miner::{Template, TemplateMiner};
 use std::io::Write;
 
-// the `onlyDataAsJson` parameter controls if the whole log line will be written
-// as json (better for computers) or just the json data (more readable for humans)
-pub fn to_json(log_lines: &Vec<LogLine>, out_file_name: &std::path::PathBuf, only_data_as_json: bool) {
-    let mut out_file = match std::fs::File::create(out_file_name) {
-        Ok(file) => file,
-        _ => {
-            eprintln!(
-                "Could not create file {} in to_json",
-                out_file_name.display()
-            );
-            std::process::exit(1);
+// Writes log lines to the json file one at a time, so the lines don't all have
+// to be in memory at once
+pub struct JsonWriter {
+    out_file: std::fs::File,
+    only_data_as_json: bool,
+    errors: Vec<String>,
+}
+
+impl JsonWriter {
+    // the `onlyDataAsJson` parameter controls if the whole log line will be written
+    // as json (better for computers) or just the json data (more readable for humans)
+    pub fn new(out_file_name: &std::path::PathBuf, only_data_as_json: bool) -> Self {
+        let out_file = match std::fs::File::create(out_file_name) {
+            Ok(file) => file,
+            _ => {
+                eprintln!(
+                    "Could not create file {} in to_json",
+                    out_file_name.display()
+                );
+                std::process::exit(1);
+            }
+        };
+        JsonWriter {
+            out_file,
+            only_data_as_json,
+            errors: Vec::new(),
         }
-    };
+    }
 
-    let mut errors = Vec::with_capacity(1024);
+    pub fn write(&mut self, l: &LogLine) {
+        self.write_with_template(l, Option::None);
+    }
 
-    if !only_data_as_json {
-        for l in log_lines {
-            if let Some(v) = l.to_json_value() {
-                write!(out_file, "{}\n", serde_json::to_string_pretty(&v).unwrap()).unwrap();
+    // The json line gets `template_id` and `template` fields, and the mixed
+    // json line starts with the template id in brackets
+    pub fn write_with_template(&mut self, l: &LogLine, template: Option<Template>) {
+        if !self.only_data_as_json {
+            if let Some(mut v) = l.to_json_value() {
+                if let Some(t) = template {
+                    v["template_id"] = serde_json::Value::String(t.id);
+                    v["template"] = serde_json::Value::String(t.text);
+                }
+                writeln!(
+                    self.out_file,
+                    "{}",
+                    serde_json::to_string_pretty(&v).unwrap()
+                )
+                .unwrap();
             } else {
-                errors.push(l);
+                self.errors.push(format!("{:?}", l));
             }
-        }
-    } else {
-        // Write mixed
-        for l in log_lines {
-            if l.write_mixed_json(&mut out_file) {
-                write!(out_file, "\n").unwrap();
+        } else {
+            // Write mixed
+            if let Some(t) = template {
+                write!(self.out_file, "[{}] ", t.id).unwrap();
+            }
+            if l.write_mixed_json(&mut self.out_file) {
+                writeln!(self.out_file).unwrap();
             } else {
-                errors.push(l);
+                self.errors.push(format!("{:?}", l));
             }
         }
     }
 
-    if !errors.is_empty() {
-        eprintln!("Error: Invalid json data >>>> ");
-        for e in &errors {
-            eprintln!("{:?}", e);
+    // Report the lines that could not be written
+    pub fn finish(&mut self) {
+        if !self.errors.is_empty() {
+            eprintln!("Error: Invalid json data >>>> ");
+            for e in &self.errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("End Invalid json data <<<< ");
         }
-        eprintln!("End Invalid json data <<<< ");
+        self.errors.clear();
+    }
+}
+
+pub fn to_json(
+    log_lines: &Vec<LogLine>,
+    out_file_name: &std::path::PathBuf,
+    only_data_as_json: bool,
+    templates: Option<&TemplateMiner>,
+) {
+    let mut writer = JsonWriter::new(out_file_name, only_data_as_json);
+    for l in log_lines {
+        let template = templates.and_then(|t| t.find(l).map(|i| t.template(i)));
+        writer.write_with_template(l, template);
     }
+    writer.finish();
 }